@@ -0,0 +1,227 @@
+//! Picking a layout for the connected monitors with `--auto`.
+//!
+//! Every profile is scored against the connected monitors, see `MatchKind`.
+//! The best profile is applied if its score reaches the threshold of the
+//! `auto` config section. Profiles whose `when` conditions do not hold are
+//! skipped. Ties go to the profile with more conditions, then to the one
+//! with the higher `priority`, then to the one applied last, then to the one
+//! declared first. Conditions and priority are inherited through `extends`.
+//! Below the threshold the fallback profile or preset is used, a row of all
+//! monitors if neither is configured.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use condition::Context;
+use database::Database;
+use preset::{Preset, PresetConfig};
+use {match_outputs, save_file, load_file, DResult, Error, MonitorConfig, Output, Profile};
+
+/// Score a profile needs to be applied if the config does not set one.
+pub const DEFAULT_THRESHOLD: u32 = 50;
+
+/// The `auto` section of the config file.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AutoConfig {
+    /// Lowest score of a profile to apply, see `MatchKind::score`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub threshold: Option<u32>,
+    /// Profile to apply when no profile scores high enough.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback_profile: Option<String>,
+    /// Layout to generate when no profile scores high enough and no fallback
+    /// profile is given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fallback: Option<PresetConfig>,
+}
+
+/// How well a profile fits the connected monitors.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MatchKind {
+    /// The profile has exactly the connected monitors.
+    Exact,
+    /// The profile has the same models, but some are other units, like the
+    /// monitors of an identical desk.
+    SameModels,
+    /// All connected monitors are in the profile, which has more.
+    Superset,
+    /// All monitors of the profile are connected, and some more.
+    Subset,
+    /// Neither contains the other.
+    None,
+}
+
+impl MatchKind {
+    pub fn score(self) -> u32 {
+        match self {
+            MatchKind::Exact => 100,
+            MatchKind::SameModels => 90,
+            MatchKind::Superset => 60,
+            MatchKind::Subset => 40,
+            MatchKind::None => 0,
+        }
+    }
+}
+
+impl fmt::Display for MatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            MatchKind::Exact => "exact match",
+            MatchKind::SameModels => "same models",
+            MatchKind::Superset => "superset",
+            MatchKind::Subset => "subset",
+            MatchKind::None => "no match",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What `--auto` decided to apply.
+#[derive(Clone, Debug)]
+pub enum Choice {
+    Profile(String, MatchKind),
+    Fallback(String),
+    Preset(PresetConfig),
+}
+
+impl fmt::Display for Choice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Choice::Profile(ref name, kind) => write!(f, "profile {} ({})", name, kind),
+            Choice::Fallback(ref name) => write!(f, "fallback profile {}", name),
+            Choice::Preset(ref preset) => write!(f, "preset {}", preset.preset),
+        }
+    }
+}
+
+/// Identifies the model of a monitor, ignoring its serial number.
+fn same_model(config: &MonitorConfig, output: &Output) -> bool {
+    match (config.monitor(), output.monitor()) {
+        (Some(a), Some(b)) => a.vendor == b.vendor && a.product_code == b.product_code,
+        _ => config.display_name == output.name,
+    }
+}
+
+/// Scores `profile` against the connected monitors.
+///
+/// Returns the profile to apply, where monitors only matched by model are
+/// changed to the connected units.
+pub fn score(profile: &Profile, connected: &[Output]) -> (MatchKind, Profile) {
+    let mut adapted = profile.clone();
+    let mut used: Vec<bool> = connected.iter().map(|_| false).collect();
+    let mut matched: Vec<bool> = profile.outputs.iter().map(|_| false).collect();
+    for (config, output) in match_outputs(profile, connected) {
+        let i = profile.outputs.iter().position(|x| std::ptr::eq(x, config)).unwrap();
+        let j = connected.iter().position(|x| std::ptr::eq(x, output)).unwrap();
+        matched[i] = true;
+        used[j] = true;
+    }
+
+    let mut other_units = 0;
+    for (config, matched) in adapted.outputs.iter_mut().zip(&mut matched) {
+        if *matched {
+            continue;
+        }
+        if let Some(j) = (0..connected.len()).find(|&j| !used[j] && same_model(config, &connected[j])) {
+            config.edid = Some(connected[j].edid.clone()).filter(|x| !x.is_empty());
+            config.display_name = connected[j].name.clone();
+            *matched = true;
+            used[j] = true;
+            other_units += 1;
+        }
+    }
+
+    let all_profile = matched.iter().all(|&x| x);
+    let all_connected = used.iter().all(|&x| x);
+    let kind = match (all_profile, all_connected) {
+        _ if connected.is_empty() || profile.outputs.is_empty() => MatchKind::None,
+        (true, true) if other_units == 0 => MatchKind::Exact,
+        (true, true) => MatchKind::SameModels,
+        (false, true) => MatchKind::Superset,
+        (true, false) => MatchKind::Subset,
+        (false, false) => MatchKind::None,
+    };
+    (kind, adapted)
+}
+
+/// When each profile was applied last, in seconds since the epoch.
+pub type LastUsed = BTreeMap<String, u64>;
+
+/// The file recording when profiles were applied, which may not exist yet.
+pub fn usage_file() -> DResult<PathBuf> {
+    let xdg_dirs = xdg::BaseDirectories::with_prefix("quickrandr")?;
+    Ok(xdg_dirs.get_state_file("last-used.yaml"))
+}
+
+pub fn load_usage(path: &Path) -> DResult<LastUsed> {
+    if fs::metadata(path).is_err() {
+        return Ok(LastUsed::new());
+    }
+    Ok(serde_yaml::from_str(&load_file(path)?)?)
+}
+
+/// Remembers that profile `name` was applied just now.
+pub fn record_usage(path: &Path, name: &str) -> DResult<()> {
+    let mut usage = load_usage(path)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|x| x.as_secs()).unwrap_or(0);
+    usage.insert(name.to_string(), now);
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    save_file(path, &serde_yaml::to_string(&usage)?)
+}
+
+struct Candidate {
+    /// Score, number of conditions, priority and last use, compared in this order.
+    rank: (u32, usize, isize, u64),
+    name: String,
+    kind: MatchKind,
+    profile: Profile,
+}
+
+/// Chooses a layout for the connected monitors, returning it resolved.
+pub fn choose(database: &Database, connected: &[Output], usage: &LastUsed, context: &Context)
+    -> DResult<(Choice, Profile)> {
+    let config = database.auto.clone().unwrap_or_default();
+    let threshold = config.threshold.unwrap_or(DEFAULT_THRESHOLD);
+
+    let mut best: Option<Candidate> = None;
+    for name in database.profiles.keys() {
+        // A broken profile should not keep other profiles from being used
+        let resolved = match database.resolve(name) {
+            Ok(x) => x,
+            Err(_) => continue,
+        };
+        let conditions = match resolved.when {
+            Some(ref when) if !when.hold(context)? => continue,
+            Some(ref when) => when.count(),
+            None => 0,
+        };
+        let (kind, adapted) = score(&resolved, connected);
+        let rank = (kind.score(), conditions, resolved.priority, usage.get(name).cloned().unwrap_or(0));
+        if kind != MatchKind::None && best.as_ref().is_none_or(|x| rank > x.rank) {
+            best = Some(Candidate { rank, name: name.clone(), kind, profile: adapted });
+        }
+    }
+
+    if let Some(best) = best {
+        if best.rank.0 >= threshold {
+            return Ok((Choice::Profile(best.name, best.kind), best.profile));
+        }
+    }
+
+    if let Some(name) = config.fallback_profile {
+        let profile = database.resolve(&name).map_err(|e| match e {
+            Error::UnknownProfile(_) => Error::InvalidProfile(format!("unknown fallback profile {}", name)),
+            e => e,
+        })?;
+        return Ok((Choice::Fallback(name), profile));
+    }
+
+    let fallback = config.fallback.unwrap_or_else(|| PresetConfig::new(Preset::ExtendRow));
+    let profile = fallback.generate(connected)?;
+    Ok((Choice::Preset(fallback), profile))
+}
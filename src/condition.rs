@@ -0,0 +1,162 @@
+//! Conditions under which `--auto` may pick a profile.
+//!
+//! ~~~yaml
+//! when:
+//!   lid: closed
+//!   ac: false
+//!   hostname: "work-*"
+//!   time: "22:00-07:00"
+//! ~~~
+//!
+//! All given conditions have to hold. The lid state is read from
+//! `/proc/acpi/button/lid/*/state` and the power supply from
+//! `/sys/class/power_supply/*/online`, below the directory named by
+//! `$QUICKRANDR_SYSTEM_ROOT` if it is set. Machines without a lid count as
+//! open, and machines without a power supply as on AC.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use rule::glob;
+use {DResult, Error};
+
+/// Environment variable naming the directory that contains `proc` and `sys`.
+pub const ROOT_VAR: &str = "QUICKRANDR_SYSTEM_ROOT";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Lid {
+    Open,
+    Closed,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Conditions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lid: Option<Lid>,
+    /// Whether the machine runs on AC power rather than battery.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ac: Option<bool>,
+    /// Glob on the host name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    /// Local time of day as `HH:MM-HH:MM`, which may wrap past midnight.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<String>,
+}
+
+/// The state of the machine the conditions are checked against.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Context {
+    pub lid: Lid,
+    pub ac: bool,
+    pub hostname: String,
+    /// Minutes since local midnight.
+    pub minutes: u32,
+}
+
+fn parse_time(s: &str) -> Option<u32> {
+    let mut iter = s.trim().splitn(2, ':');
+    let hours: u32 = iter.next()?.parse().ok()?;
+    let minutes: u32 = iter.next()?.parse().ok()?;
+    if hours < 24 && minutes < 60 {
+        Some(hours * 60 + minutes)
+    } else {
+        None
+    }
+}
+
+fn parse_window(s: &str) -> DResult<(u32, u32)> {
+    let mut iter = s.splitn(2, '-');
+    match (iter.next().and_then(parse_time), iter.next().and_then(parse_time)) {
+        (Some(start), Some(end)) => Ok((start, end)),
+        _ => Err(Error::InvalidProfile(format!("time window {}, expected HH:MM-HH:MM", s))),
+    }
+}
+
+impl Conditions {
+    /// Number of conditions given, more specific profiles win ties.
+    pub fn count(&self) -> usize {
+        self.lid.is_some() as usize
+            + self.ac.is_some() as usize
+            + self.hostname.is_some() as usize
+            + self.time.is_some() as usize
+    }
+
+    pub fn validate(&self) -> DResult<()> {
+        if let Some(ref hostname) = self.hostname {
+            glob(hostname, true).map_err(|e| Error::InvalidProfile(format!("hostname pattern {}: {}", hostname, e)))?;
+        }
+        if let Some(ref time) = self.time {
+            parse_window(time)?;
+        }
+        Ok(())
+    }
+
+    pub fn hold(&self, context: &Context) -> DResult<bool> {
+        self.validate()?;
+        let in_window = |time: &String| {
+            let (start, end) = parse_window(time).unwrap_or((0, 0));
+            if start <= end {
+                start <= context.minutes && context.minutes < end
+            } else {
+                start <= context.minutes || context.minutes < end
+            }
+        };
+        Ok(self.lid.is_none_or(|x| x == context.lid)
+            && self.ac.is_none_or(|x| x == context.ac)
+            && self.hostname.as_ref().is_none_or(|x| glob(x, true).is_ok_and(|re| re.is_match(&context.hostname)))
+            && self.time.as_ref().is_none_or(in_window))
+    }
+}
+
+/// Contents of the files matching `dir/*/name`, in no particular order.
+fn read_all(dir: &Path, name: &str) -> Vec<String> {
+    fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .filter_map(|x| x.ok())
+                .filter_map(|x| fs::read_to_string(x.path().join(name)).ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn local_minutes() -> u32 {
+    // SAFETY: `tm` is plain data, and both pointers are valid for the calls
+    unsafe {
+        let now = libc::time(ptr::null_mut());
+        let mut tm: libc::tm = std::mem::zeroed();
+        if libc::localtime_r(&now, &mut tm).is_null() {
+            return 0;
+        }
+        (tm.tm_hour * 60 + tm.tm_min) as u32
+    }
+}
+
+impl Context {
+    /// Reads the state of this machine, below `$QUICKRANDR_SYSTEM_ROOT` if set.
+    pub fn current() -> Context {
+        let root = env::var_os(ROOT_VAR).map(PathBuf::from).unwrap_or_else(|| PathBuf::from("/"));
+        Context::read(&root, local_minutes())
+    }
+
+    /// Reads the machine state from the `proc` and `sys` trees below `root`.
+    pub fn read(root: &Path, minutes: u32) -> Context {
+        let closed = read_all(&root.join("proc/acpi/button/lid"), "state")
+            .iter()
+            .any(|x| x.contains("closed"));
+        let supplies = read_all(&root.join("sys/class/power_supply"), "online");
+        let ac = supplies.is_empty() || supplies.iter().any(|x| x.trim() == "1");
+        let hostname = fs::read_to_string(root.join("proc/sys/kernel/hostname")).unwrap_or_default();
+
+        Context {
+            lid: if closed { Lid::Closed } else { Lid::Open },
+            ac,
+            hostname: hostname.trim().to_string(),
+            minutes,
+        }
+    }
+}
@@ -0,0 +1,422 @@
+//! The profile database, a yaml file of named display layouts.
+//!
+//! Profiles are read from several layered files, lowest priority first:
+//!
+//! 1. `quickrandr/config.yaml` and `quickrandr/conf.d/*.yaml` in every
+//!    `XDG_CONFIG_DIRS` entry, e.g. `/etc/xdg`, least preferred entry first.
+//! 2. `config.yaml` and `conf.d/*.yaml` in `$QUICKRANDR_TEAM_DIR`.
+//! 3. `conf.d/*.yaml` in the users config directory.
+//! 4. The users `config.yaml`, which receives all changes.
+//!
+//! Files in a `conf.d` directory are merged in file name order. A profile
+//! defined in a later file replaces a profile of the same name as a whole,
+//! and so do the `auto`, `hooks` and `dpi` sections.
+//! Every file may also be written as `.toml` or `.json` instead.
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use indexmap::IndexMap;
+
+use auto::AutoConfig;
+use dpi::DpiConfig;
+use hooks::Hooks;
+use super::{DResult, Error, FileLock, Profile, load_file, save_file};
+use format::Format;
+use yaml_edit::Document;
+
+/// Environment variable naming a shared directory of profiles.
+pub const TEAM_DIR_VAR: &str = "QUICKRANDR_TEAM_DIR";
+
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct Database {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto: Option<AutoConfig>,
+    /// Commands to run around applying any profile or preset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks: Option<Hooks>,
+    /// How to set the DPI after applying any profile or preset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dpi: Option<DpiConfig>,
+    /// Profiles in the order they are declared in the file.
+    #[serde(default)]
+    pub profiles: IndexMap<String, Profile>,
+}
+
+impl Database {
+    /// Loads the database at `path`, or an empty one if it does not exist yet.
+    pub fn load(path: &Path) -> DResult<Database> {
+        if fs::metadata(path).is_err() {
+            return Ok(Database::default());
+        }
+        Database::parse(&load_file(path)?, Format::from_path(path))
+    }
+
+    pub fn parse(s: &str, format: Format) -> DResult<Database> {
+        format.parse(s)
+    }
+
+    pub fn generate(&self, format: Format) -> DResult<String> {
+        format.generate(self)
+    }
+
+    /// Renders the database for writing it to `path`.
+    ///
+    /// An existing yaml file is patched in place, so comments and the
+    /// formatting of unchanged profiles survive. `renames` lists the
+    /// profiles renamed since the file was loaded.
+    pub fn generate_for(&self, path: &Path, renames: &[(String, String)]) -> DResult<String> {
+        let format = Format::from_path(path);
+        if format == Format::Yaml {
+            let text = load_file(path).ok();
+            if let Some(mut document) = text.as_ref().and_then(|x| Document::parse(x)) {
+                // What the file stands for, to tell which parts were changed
+                let old = text.as_ref()
+                    .and_then(|x| Database::parse(x, format).ok())
+                    .and_then(|x| serde_yaml::to_value(x).ok());
+                for (from, to) in renames {
+                    document.rename_key(&["profiles"], from, to);
+                }
+                if let Some(text) = document.update(old.as_ref(), &serde_yaml::to_value(self)?) {
+                    return Ok(text);
+                }
+            }
+        }
+        self.generate(format)
+    }
+
+    /// Writes the database in the format matching the extension of `path`.
+    pub fn save(&self, path: &Path) -> DResult<()> {
+        save_file(path, &self.generate_for(path, &[])?)
+    }
+
+    pub fn get(&self, name: &str) -> DResult<&Profile> {
+        self.profiles
+            .get(name)
+            .ok_or_else(|| Error::UnknownProfile(name.to_string()))
+    }
+
+    /// Adds a profile, replacing an existing one of the same name only if `force` is set.
+    pub fn insert(&mut self, name: &str, profile: Profile, force: bool) -> DResult<()> {
+        if !force && self.profiles.contains_key(name) {
+            return Err(Error::ProfileExists(name.to_string()));
+        }
+        self.profiles.insert(name.to_string(), profile);
+        Ok(())
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> DResult<&mut Profile> {
+        self.profiles
+            .get_mut(name)
+            .ok_or_else(|| Error::UnknownProfile(name.to_string()))
+    }
+
+    pub fn remove(&mut self, name: &str) -> DResult<Profile> {
+        self.profiles
+            .shift_remove(name)
+            .ok_or_else(|| Error::UnknownProfile(name.to_string()))
+    }
+
+    /// Renames a profile, keeping its position in the file.
+    pub fn rename(&mut self, from: &str, to: &str, force: bool) -> DResult<()> {
+        self.get(from)?;
+        if from == to {
+            return Ok(());
+        }
+        if !force && self.profiles.contains_key(to) {
+            return Err(Error::ProfileExists(to.to_string()));
+        }
+        self.profiles.shift_remove(to);
+        let index = self.profiles.get_index_of(from).expect("profile to rename");
+        let (_, profile) = self.profiles.shift_remove_index(index).expect("profile to rename");
+        self.profiles.shift_insert(index, to.to_string(), profile);
+        Ok(())
+    }
+
+    /// Returns profile `name` merged with all profiles it extends.
+    pub fn resolve(&self, name: &str) -> DResult<Profile> {
+        self.resolve_chain(self.get(name)?, &mut vec![name.to_string()])
+    }
+
+    /// Profile `name` as a layout file that does not need its parents.
+    pub fn export(&self, name: &str, format: Format) -> DResult<String> {
+        format.generate(&self.resolve(name)?)
+    }
+
+    /// Merges a profile that is not part of the database with its parents.
+    pub fn resolve_profile(&self, profile: &Profile) -> DResult<Profile> {
+        self.resolve_chain(profile, &mut Vec::new())
+    }
+
+    fn resolve_chain(&self, profile: &Profile, seen: &mut Vec<String>) -> DResult<Profile> {
+        if let Some(ref when) = profile.when {
+            when.validate()?;
+        }
+        let mut resolved = match profile.extends {
+            Some(ref parent) => {
+                if seen.contains(parent) {
+                    seen.push(parent.clone());
+                    return Err(Error::InvalidProfile(format!("inheritance cycle {}", seen.join(" -> "))));
+                }
+                seen.push(parent.clone());
+                self.resolve_chain(self.get(parent)?, seen)?
+            }
+            None => Profile::default(),
+        };
+
+        if profile.priority != 0 {
+            resolved.priority = profile.priority;
+        }
+        if profile.when.is_some() {
+            resolved.when = profile.when.clone();
+        }
+        if profile.hooks.is_some() {
+            resolved.hooks = profile.hooks.clone();
+        }
+        if profile.others.is_some() {
+            resolved.others = profile.others;
+        }
+        if profile.refresh.is_some() {
+            resolved.refresh = profile.refresh;
+        }
+        if !profile.primary_order.is_empty() {
+            resolved.primary_order = profile.primary_order.clone();
+        }
+        resolved.dpi = match (&resolved.dpi, &profile.dpi) {
+            (Some(a), Some(b)) => Some(a.merge(b)),
+            (a, b) => b.clone().or_else(|| a.clone()),
+        };
+
+        // Monitors listed again in the child replace the inherited ones
+        for config in &profile.outputs {
+            if let Some(ref rule) = config.rule {
+                rule.validate()?;
+            }
+            match resolved.outputs.iter().position(|x| x.same_monitor(config)) {
+                Some(i) => resolved.outputs[i] = config.clone(),
+                None => resolved.outputs.push(config.clone()),
+            }
+        }
+
+        for x in &profile.overrides {
+            let mut found = false;
+            for config in resolved.outputs.iter_mut().filter(|c| x.matches(c)) {
+                x.apply(config);
+                found = true;
+            }
+            if !found {
+                return Err(Error::InvalidProfile(format!("override of {} does not match any monitor", x.output)));
+            }
+        }
+
+        Ok(resolved)
+    }
+
+    pub fn copy(&mut self, from: &str, to: &str, force: bool) -> DResult<()> {
+        let profile = self.get(from)?.clone();
+        self.insert(to, profile, force)
+    }
+}
+
+/// The config files making up the merged profile database.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigSources {
+    /// Read-only files, lowest priority first.
+    pub layers: Vec<PathBuf>,
+    /// The per-user database, which has the highest priority.
+    pub user: PathBuf,
+}
+
+impl ConfigSources {
+    /// Uses only the given file, without any shared layers.
+    pub fn single(user: PathBuf) -> ConfigSources {
+        ConfigSources {
+            layers: Vec::new(),
+            user,
+        }
+    }
+
+    /// Looks up all layers in the XDG config directories and the team directory.
+    pub fn discover() -> DResult<ConfigSources> {
+        let xdg_dirs = xdg::BaseDirectories::with_prefix("quickrandr")?;
+        let team_dir = env::var_os(TEAM_DIR_VAR).map(PathBuf::from);
+
+        Ok(ConfigSources::from_dirs(&xdg_dirs.get_config_dirs(),
+                                    team_dir.as_deref(),
+                                    &xdg_dirs.get_config_home(),
+                                    super::xdg_config_file()?))
+    }
+
+    /// Collects the existing layers of the given directories.
+    ///
+    /// `system_dirs` is ordered by preference, like `XDG_CONFIG_DIRS`.
+    pub fn from_dirs(system_dirs: &[PathBuf], team_dir: Option<&Path>, user_dir: &Path, user: PathBuf) -> ConfigSources {
+        let mut layers = Vec::new();
+
+        for dir in system_dirs.iter().rev() {
+            layers.extend(directory_layers(dir, true));
+        }
+        if let Some(dir) = team_dir {
+            layers.extend(directory_layers(dir, true));
+        }
+        layers.extend(directory_layers(user_dir, false));
+        layers.retain(|x| *x != user);
+
+        ConfigSources { layers, user }
+    }
+
+    /// Locks the per-user database against concurrent changes.
+    ///
+    /// Take the lock before loading a database that is going to be written
+    /// back, and keep it until the write is done.
+    pub fn lock(&self) -> DResult<FileLock> {
+        let mut lock_path = self.user.clone().into_os_string();
+        lock_path.push(".lock");
+        FileLock::acquire(Path::new(&lock_path))
+    }
+
+    pub fn load(&self) -> DResult<LayeredDatabase> {
+        let mut layers = Vec::new();
+        for path in self.layers.iter().chain(Some(&self.user)) {
+            let database = Database::load(path).map_err(|e| match e {
+                Error::Io(_) | Error::Xdg(_) => e,
+                e => Error::InvalidProfile(format!("{}: {}", path.display(), e)),
+            })?;
+            layers.push((path.clone(), database));
+        }
+        Ok(LayeredDatabase { layers, renames: Vec::new() })
+    }
+}
+
+fn directory_layers(dir: &Path, with_main: bool) -> Vec<PathBuf> {
+    let mut layers = Vec::new();
+
+    if with_main {
+        layers.extend(Format::ALL
+            .iter()
+            .map(|x| dir.join(format!("config.{}", x.extension())))
+            .filter(|x| x.is_file()));
+    }
+
+    let mut drop_ins: Vec<PathBuf> = fs::read_dir(dir.join("conf.d"))
+        .map(|entries| {
+            entries
+                .filter_map(|x| x.ok().map(|x| x.path()))
+                .filter(|x| x.is_file() && x.extension().and_then(|e| e.to_str()).and_then(Format::from_name).is_some())
+                .collect()
+        })
+        .unwrap_or_default();
+    drop_ins.sort();
+    layers.extend(drop_ins);
+
+    layers
+}
+
+/// All config files with their profiles, with the per-user database last.
+#[derive(Clone, Debug)]
+pub struct LayeredDatabase {
+    pub layers: Vec<(PathBuf, Database)>,
+    renames: Vec<(String, String)>,
+}
+
+impl LayeredDatabase {
+    /// Combines all layers into one database, later layers replacing earlier profiles.
+    pub fn merged(&self) -> Database {
+        let mut merged = Database::default();
+        for (_, database) in &self.layers {
+            if database.auto.is_some() {
+                merged.auto = database.auto.clone();
+            }
+            if database.hooks.is_some() {
+                merged.hooks = database.hooks.clone();
+            }
+            if database.dpi.is_some() {
+                merged.dpi = database.dpi.clone();
+            }
+            merged.profiles.extend(database.profiles.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        merged
+    }
+
+    /// The files defining profile `name`, the effective one last.
+    pub fn origins(&self, name: &str) -> Vec<&Path> {
+        self.layers
+            .iter()
+            .filter(|x| x.1.profiles.contains_key(name))
+            .map(|x| x.0.as_path())
+            .collect()
+    }
+
+    pub fn user(&self) -> &Database {
+        &self.layers.last().expect("user database").1
+    }
+
+    pub fn user_path(&self) -> &Path {
+        &self.layers.last().expect("user database").0
+    }
+
+    fn user_mut(&mut self) -> &mut Database {
+        &mut self.layers.last_mut().expect("user database").1
+    }
+
+    fn check_writable(&self, name: &str) -> DResult<()> {
+        if self.user().profiles.contains_key(name) {
+            return Ok(());
+        }
+        match self.origins(name).last() {
+            Some(origin) => Err(Error::ReadOnly(format!("profile {} is defined in {}", name, origin.display()))),
+            None => Err(Error::UnknownProfile(name.to_string())),
+        }
+    }
+
+    fn check_free(&self, name: &str, force: bool) -> DResult<()> {
+        if !force && !self.origins(name).is_empty() {
+            return Err(Error::ProfileExists(name.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Adds a profile to the per-user database.
+    pub fn insert(&mut self, name: &str, profile: Profile, force: bool) -> DResult<()> {
+        self.check_free(name, force)?;
+        self.user_mut().insert(name, profile, true)
+    }
+
+    pub fn remove(&mut self, name: &str) -> DResult<Profile> {
+        self.check_writable(name)?;
+        self.user_mut().remove(name)
+    }
+
+    pub fn rename(&mut self, from: &str, to: &str, force: bool) -> DResult<()> {
+        self.check_writable(from)?;
+        if from != to {
+            self.check_free(to, force)?;
+        }
+        self.user_mut().rename(from, to, true)?;
+        self.renames.push((from.to_string(), to.to_string()));
+        Ok(())
+    }
+
+    /// Gives write access to a profile of the per-user database.
+    pub fn get_mut(&mut self, name: &str) -> DResult<&mut Profile> {
+        self.check_writable(name)?;
+        self.user_mut().get_mut(name)
+    }
+
+    /// Copies a profile of any layer into the per-user database.
+    pub fn copy(&mut self, from: &str, to: &str, force: bool) -> DResult<()> {
+        let profile = self.merged().get(from)?.clone();
+        self.insert(to, profile, force)
+    }
+
+    /// Renders the per-user database as it would be written back to disk.
+    pub fn generate(&self) -> DResult<String> {
+        self.user().generate_for(self.user_path(), &self.renames)
+    }
+
+    /// Writes the per-user database back to disk.
+    pub fn save(&self) -> DResult<()> {
+        save_file(self.user_path(), &self.generate()?)
+    }
+}
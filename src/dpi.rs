@@ -0,0 +1,161 @@
+//! Font DPI matching the pixel density of the monitors.
+//!
+//! ~~~yaml
+//! dpi:
+//!   output: DP-2
+//!   xrandr: true
+//!   xrdb: true
+//!   cursor: true
+//! ~~~
+//!
+//! After a layout is applied, the DPI of the primary output is computed from
+//! its resolution and physical size, as reported by xrandr or the EDID. The
+//! `dpi` section can be given for all profiles at the top of the config file,
+//! and fields set in a profile override it. Nothing is changed unless
+//! `xrandr` or `xrdb` is enabled.
+//!
+//! With a `target`, every monitor without an explicit `scale` is scaled so
+//! it reaches that DPI, which evens out monitors of different pixel density.
+//! Monitors at absolute positions are moved to fit the scaled sizes.
+
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use placement;
+use {DResult, Error, MonitorConfig, Output, Plan, PlannedOutput};
+
+/// The DPI X assumes when nothing else is known.
+pub const BASE_DPI: u32 = 96;
+
+/// Cursor size in pixels at `BASE_DPI`.
+pub const BASE_CURSOR_SIZE: u32 = 24;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct DpiConfig {
+    /// Output whose pixel density is used, by connector, model or serial.
+    /// The primary output, or the first enabled one, if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Fixed DPI to use instead of the computed one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub value: Option<u32>,
+    /// DPI to scale all monitors to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target: Option<u32>,
+    /// Passes the DPI to `xrandr --dpi`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xrandr: Option<bool>,
+    /// Merges `Xft.dpi` into the X resources with `xrdb`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub xrdb: Option<bool>,
+    /// Also merges `Xcursor.size`, scaled along with the DPI.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<bool>,
+}
+
+impl DpiConfig {
+    /// Combines the global config with the fields a profile sets.
+    pub fn merge(&self, profile: &DpiConfig) -> DpiConfig {
+        DpiConfig {
+            output: profile.output.clone().or_else(|| self.output.clone()),
+            value: profile.value.or(self.value),
+            target: profile.target.or(self.target),
+            xrandr: profile.xrandr.or(self.xrandr),
+            xrdb: profile.xrdb.or(self.xrdb),
+            cursor: profile.cursor.or(self.cursor),
+        }
+    }
+
+    /// The DPI to use for the outputs enabled by `plan`.
+    pub fn dpi(&self, plan: &Plan, connected: &[Output]) -> Option<u32> {
+        if self.value.is_some() {
+            return self.value;
+        }
+
+        let planned = match self.output {
+            Some(ref selector) => plan.outputs.iter().find(|x| {
+                connected.iter().any(|o| o.connection_name == x.output && o.selected_by(selector))
+            }),
+            None => plan.outputs.iter().find(|x| x.primary).or_else(|| plan.outputs.first()),
+        }?;
+        let output = connected.iter().find(|x| x.connection_name == planned.output)?;
+        output_dpi(output, planned).map(|x| x.round() as u32)
+    }
+}
+
+/// Pixel density of `output` once it shows `planned`, after scaling.
+pub fn output_dpi(output: &Output, planned: &PlannedOutput) -> Option<f64> {
+    pixel_density(output, (planned.width, planned.height))
+}
+
+/// Pixel density of `output` with `size` pixels on screen, along the
+/// diagonal, which rotation does not change.
+pub fn pixel_density(output: &Output, size: (isize, isize)) -> Option<f64> {
+    let (width_mm, height_mm) = output.physical_size_mm()?;
+    let pixels = ((size.0 * size.0 + size.1 * size.1) as f64).sqrt();
+    let inches = (f64::from(width_mm * width_mm + height_mm * height_mm)).sqrt() / 25.4;
+    Some(pixels / inches)
+}
+
+/// Scales each monitor of `configs`, shown on the output of the same index,
+/// to `target` DPI, and moves the monitors at absolute positions to fit.
+///
+/// Monitors with a `scale` or `scale_from` of their own, or of unknown size,
+/// are left alone.
+pub fn scale_to_target(configs: &mut [MonitorConfig], outputs: &[&Output], target: u32) -> DResult<()> {
+    let old: Vec<(isize, isize)> = configs.iter().map(placement::screen_size).collect::<DResult<_>>()?;
+    for ((config, output), &size) in configs.iter_mut().zip(outputs).zip(&old) {
+        if config.scale.is_some() || config.scale_from.is_some() {
+            continue;
+        }
+        if let Some(dpi) = pixel_density(output, size) {
+            config.scale = Some((f64::from(target) / dpi * 100.0).round() / 100.0);
+        }
+    }
+    let new: Vec<(isize, isize)> = configs.iter().map(placement::screen_size).collect::<DResult<_>>()?;
+
+    let mut absolute = Vec::new();
+    let mut positions = Vec::new();
+    for (i, config) in configs.iter().enumerate() {
+        if config.relation()?.is_some() {
+            continue;
+        }
+        absolute.push(i);
+        positions.push(config.position.unwrap_or_default().into());
+    }
+    let old: Vec<(isize, isize)> = absolute.iter().map(|&i| old[i]).collect();
+    let new: Vec<(isize, isize)> = absolute.iter().map(|&i| new[i]).collect();
+    for (&i, position) in absolute.iter().zip(placement::reflow(&positions, &old, &new)) {
+        configs[i].position = Some(position.into());
+    }
+    Ok(())
+}
+
+/// X resources setting the font DPI, and the cursor size if `cursor` is set.
+pub fn resources(dpi: u32, cursor: bool) -> String {
+    let mut resources = format!("Xft.dpi: {}\n", dpi);
+    if cursor {
+        let size = (BASE_CURSOR_SIZE * dpi + BASE_DPI / 2) / BASE_DPI;
+        resources.push_str(&format!("Xcursor.size: {}\n", size));
+    }
+    resources
+}
+
+/// Merges `resources` into the resource database of the X server.
+pub fn merge_xrdb(resources: &str, debug: bool) -> DResult<()> {
+    print!("xrdb -merge <<EOF\n{}EOF\n", resources);
+    if debug {
+        return Ok(());
+    }
+
+    let mut child = Command::new("xrdb")
+        .arg("-merge")
+        .stdin(Stdio::piped())
+        .spawn()?;
+    child.stdin.take().expect("piped stdin").write_all(resources.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(Error::Xrdb(format!("xrdb -merge exited with {}", status)));
+    }
+    Ok(())
+}
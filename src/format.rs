@@ -0,0 +1,69 @@
+//! Selection of the serialization format by file extension.
+
+use std::fmt;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::DResult;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl Format {
+    /// All formats, in the order config files are looked up.
+    pub const ALL: [Format; 3] = [Format::Yaml, Format::Toml, Format::Json];
+
+    /// Picks the format from the file extension, defaulting to yaml.
+    pub fn from_path(path: &Path) -> Format {
+        path.extension()
+            .and_then(|x| x.to_str())
+            .and_then(Format::from_name)
+            .unwrap_or(Format::Yaml)
+    }
+
+    /// Parses a format name or file extension.
+    pub fn from_name(name: &str) -> Option<Format> {
+        match &name.to_lowercase()[..] {
+            "yaml" | "yml" => Some(Format::Yaml),
+            "toml" => Some(Format::Toml),
+            "json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            Format::Yaml => "yaml",
+            Format::Toml => "toml",
+            Format::Json => "json",
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self, s: &str) -> DResult<T> {
+        Ok(match *self {
+            Format::Yaml => serde_yaml::from_str(s)?,
+            Format::Toml => toml::from_str(s)?,
+            Format::Json => serde_json::from_str(s)?,
+        })
+    }
+
+    pub fn generate<T: Serialize>(&self, value: &T) -> DResult<String> {
+        Ok(match *self {
+            Format::Yaml => serde_yaml::to_string(value)?,
+            Format::Toml => toml::to_string_pretty(value)?,
+            Format::Json => serde_json::to_string_pretty(value)? + "\n",
+        })
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.extension())
+    }
+}
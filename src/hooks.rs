@@ -0,0 +1,107 @@
+//! Commands run before and after a layout is applied.
+//!
+//! ~~~yaml
+//! hooks:
+//!   timeout: 10
+//!   pre: ["polybar-msg cmd quit"]
+//!   post: ["feh --bg-scale ~/wallpaper.png", "xset r rate 200 30"]
+//! ~~~
+//!
+//! Hooks can be given for all profiles at the top of the config file, and
+//! for a single profile, which inherits the hooks of the profile it extends
+//! unless it has its own. The global pre hooks run first and the global post
+//! hooks last. Every command runs with `sh -c` and these variables:
+//!
+//! - `QUICKRANDR_HOOK`: `pre` or `post`
+//! - `QUICKRANDR_PROFILE`: name of the profile or preset
+//! - `QUICKRANDR_OUTPUTS`: the enabled outputs, separated by spaces
+//! - `QUICKRANDR_PRIMARY`: the primary output, empty if there is none
+//! - `QUICKRANDR_GEOMETRY`: a JSON list with the connector, position, size,
+//!   rotation and primary flag of every enabled output
+//!
+//! A pre hook that fails or times out aborts the apply, a failing post hook
+//! is only reported.
+
+use std::process::{Child, Command};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use {DResult, Error, Plan};
+
+/// Seconds a hook may run if the config does not say otherwise.
+pub const DEFAULT_TIMEOUT: u64 = 30;
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Hooks {
+    /// Seconds each command may run before it is killed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timeout: Option<u64>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post: Vec<String>,
+}
+
+/// Waits for `child`, killing it after `timeout`.
+fn wait(child: &mut Child, timeout: Duration) -> DResult<Option<i32>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status.code().unwrap_or(-1)));
+        }
+        if Instant::now() >= deadline {
+            child.kill()?;
+            child.wait()?;
+            return Ok(None);
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+fn run_hook(stage: &str, command: &str, timeout: u64, name: &str, plan: &Plan, debug: bool) -> DResult<()> {
+    if debug {
+        println!("DEBUG: Run {} hook: {}", stage, command);
+        return Ok(());
+    }
+
+    let outputs: Vec<&str> = plan.outputs.iter().map(|x| x.output.as_str()).collect();
+    let primary = plan.outputs.iter().find(|x| x.primary).map(|x| x.output.as_str()).unwrap_or("");
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .env("QUICKRANDR_HOOK", stage)
+        .env("QUICKRANDR_PROFILE", name)
+        .env("QUICKRANDR_OUTPUTS", outputs.join(" "))
+        .env("QUICKRANDR_PRIMARY", primary)
+        .env("QUICKRANDR_GEOMETRY", serde_json::to_string(&plan.outputs)?)
+        .spawn()?;
+
+    match wait(&mut child, Duration::from_secs(timeout))? {
+        Some(0) => Ok(()),
+        Some(code) => Err(Error::Hook(format!("{} hook `{}` failed with exit code {}", stage, command, code))),
+        None => Err(Error::Hook(format!("{} hook `{}` timed out after {}s", stage, command, timeout))),
+    }
+}
+
+/// Runs the pre hooks of every entry of `hooks` in order, stopping at the first failure.
+pub fn run_pre_hooks(name: &str, plan: &Plan, hooks: &[&Hooks], debug: bool) -> DResult<()> {
+    for x in hooks {
+        for command in &x.pre {
+            run_hook("pre", command, x.timeout.unwrap_or(DEFAULT_TIMEOUT), name, plan, debug)?;
+        }
+    }
+    Ok(())
+}
+
+/// Runs the post hooks of every entry of `hooks` in reverse order, returning the failures.
+pub fn run_post_hooks(name: &str, plan: &Plan, hooks: &[&Hooks], debug: bool) -> Vec<Error> {
+    let mut errors = Vec::new();
+    for x in hooks.iter().rev() {
+        for command in &x.post {
+            if let Err(e) = run_hook("post", command, x.timeout.unwrap_or(DEFAULT_TIMEOUT), name, plan, debug) {
+                errors.push(e);
+            }
+        }
+    }
+    errors
+}
@@ -0,0 +1,199 @@
+//! Conversion of the old json config file.
+//!
+//! Its profiles were xrandr arguments per connector, passed on as they were.
+//! They are parsed into `MonitorConfig`s here, so they are checked when they
+//! are converted rather than when xrandr runs them, and the remembered
+//! layouts of `autoprofiles` become profiles `auto-1`, `auto-2` and so on.
+
+use std::convert::TryFrom;
+
+use database::Database;
+use others::Others;
+use placement::Side;
+use rule::MatchRule;
+use transform::{Panning, Reflect, Transform};
+use {capture_output, ConfigFile, DResult, Error, Filter, MonitorConfig, Orientation, Profile, RawProfile};
+
+/// Splits xrandr arguments like a shell, keeping quoted words together.
+pub fn split_args(args: &str) -> DResult<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    let mut chars = args.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') | (None, '\\') => {
+                let escaped = chars.next().ok_or_else(|| invalid(args, "ends with a backslash"))?;
+                word.get_or_insert_with(String::new).push(escaped);
+            }
+            (Some(_), c) => word.get_or_insert_with(String::new).push(c),
+            (None, '"') | (None, '\'') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (None, c) if c.is_whitespace() => words.extend(word.take()),
+            (None, c) => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if quote.is_some() {
+        return Err(invalid(args, "has an unclosed quote"));
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+fn invalid(args: &str, problem: &str) -> Error {
+    Error::InvalidProfile(format!("xrandr arguments `{}` {}", args, problem))
+}
+
+/// Parses the xrandr arguments of a single output, like
+/// `--mode 1920x1080 --rate 60 --right-of LVDS1 --primary`.
+///
+/// The monitor is matched by its connector, as the old profiles did.
+/// `--auto` and `--preferred` leave out the mode, so the preferred mode is
+/// used when the profile is applied.
+pub fn parse_output_args(connector: &str, args: &str) -> DResult<MonitorConfig> {
+    let mut config = MonitorConfig {
+        display_name: connector.to_string(),
+        connector: Some(connector.to_string()),
+        rule: Some(MatchRule { connector: Some(connector.to_string()), ..MatchRule::default() }),
+        enabled: true,
+        ..MonitorConfig::default()
+    };
+
+    let words = split_args(args)?;
+    let mut iter = words.iter();
+    while let Some(option) = iter.next() {
+        let mut value = || iter.next().ok_or_else(|| invalid(args, &format!("lacks the value of {}", option)));
+        match &option[..] {
+            "--auto" | "--preferred" => config.mode = None,
+            "--off" => config.enabled = false,
+            "--primary" => config.primary = true,
+            "--mode" => config.mode = Some(value()?.parse().map_err(|e: String| invalid(args, &e))?),
+            "--rate" | "--refresh" => {
+                let rate = value()?;
+                config.rate = Some(rate.parse().map_err(|_| invalid(args, &format!("has an invalid rate {}", rate)))?);
+            }
+            "--pos" => {
+                let position = value()?.parse().map_err(|e: String| invalid(args, &e))?;
+                config.clear_placement();
+                config.position = Some(position);
+            }
+            "--rotate" | "--orientation" => {
+                let name = value()?;
+                config.orientation = Orientation::from_name(name)
+                    .ok_or_else(|| invalid(args, &format!("has an unknown rotation {}", name)))?;
+            }
+            "--reflect" => {
+                let name = value()?;
+                config.reflect = match &name[..] {
+                    "normal" => None,
+                    _ => Some(Reflect::from_name(name)
+                        .ok_or_else(|| invalid(args, &format!("has an unknown reflection {}", name)))?),
+                };
+            }
+            "--scale" => {
+                let scale = value()?;
+                let factors: Option<Vec<f64>> = scale.split('x').map(|x| x.parse().ok()).collect();
+                match factors.as_deref() {
+                    Some(&[s]) => config.scale = Some(s),
+                    Some(&[sx, sy]) if sx == sy => config.scale = Some(sx),
+                    Some(&[sx, sy]) => config.transform = Some(Transform([sx, 0.0, 0.0, 0.0, sy, 0.0, 0.0, 0.0, 1.0])),
+                    _ => return Err(invalid(args, &format!("has an invalid scale {}", scale))),
+                }
+            }
+            "--scale-from" => config.scale_from = Some(value()?.clone()),
+            "--transform" => {
+                let matrix = value()?;
+                config.transform = match &matrix[..] {
+                    "none" => None,
+                    _ => Some(Transform::try_from(matrix.clone()).map_err(|e| invalid(args, &e))?),
+                };
+            }
+            "--filter" => {
+                let name = value()?;
+                config.filter = Some(Filter::from_name(name)
+                    .ok_or_else(|| invalid(args, &format!("has an unknown filter {}", name)))?);
+            }
+            "--panning" => {
+                let panning = value()?;
+                config.panning = match &panning[..] {
+                    "0x0" => None,
+                    _ => Some(Panning::try_from(panning.clone()).map_err(|e| invalid(args, &e))?),
+                };
+            }
+            "--set" => {
+                let property = value()?.clone();
+                let setting = iter.next().ok_or_else(|| invalid(args, &format!("lacks the value of {}", property)))?;
+                config.properties.insert(property, setting.clone());
+            }
+            _ => {
+                let side = option.strip_prefix("--")
+                    .map(|x| x.replace('-', "_"))
+                    .and_then(|x| Side::from_name(&x))
+                    .ok_or_else(|| invalid(args, &format!("has an unsupported option {}", option)))?;
+                let target = value()?;
+                config.place(side, target);
+            }
+        }
+    }
+    Ok(config)
+}
+
+/// Parses the `other_outputs` arguments of the old config file into the
+/// `others` policy doing the same.
+///
+/// Only `--auto`, `--preferred` and `--off` have one. All other outputs were
+/// given the same arguments, so e.g. `--right-of LVDS1` stacked them on the
+/// same spot, which no policy does.
+pub fn parse_other_args(args: &str) -> DResult<Others> {
+    let config = parse_output_args("other", args)?;
+    let words = split_args(args)?;
+    if let Some(option) = words.iter().find(|x| !["--auto", "--preferred", "--off"].contains(&&x[..])) {
+        return Err(invalid(args, &format!("has no `others` policy for {}", option)));
+    }
+    Ok(if words.is_empty() {
+        Others::Keep
+    } else if !config.enabled {
+        Others::Off
+    } else {
+        Others::Auto
+    })
+}
+
+/// Converts a profile of the old config file.
+pub fn convert_profile(raw: &RawProfile) -> DResult<Profile> {
+    let outputs = raw.outputs
+        .iter()
+        .map(|(connector, args)| parse_output_args(connector, args))
+        .collect::<DResult<_>>()?;
+    Ok(Profile {
+        outputs,
+        others: Some(parse_other_args(&raw.other_outputs)?),
+        ..Profile::default()
+    })
+}
+
+/// Converts the old config file into a profile database.
+pub fn convert(config: &ConfigFile) -> DResult<Database> {
+    let mut database = Database::default();
+    for (i, layout) in config.autoprofiles.iter().enumerate() {
+        let outputs = layout
+            .iter()
+            .filter_map(|(connector, output)| {
+                let mut output = output.clone();
+                output.connection_name = connector.clone();
+                if output.name.is_empty() {
+                    output.name = output.monitor().map_or_else(|| connector.clone(), |x| x.model);
+                }
+                capture_output(&output)
+            })
+            .collect();
+        database.insert(&format!("auto-{}", i + 1), Profile { outputs, ..Profile::default() }, false)?;
+    }
+    for (name, raw) in &config.profiles {
+        database.insert(name, convert_profile(raw)?, true)?;
+    }
+    Ok(database)
+}
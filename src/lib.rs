@@ -1,53 +1,197 @@
 #[macro_use]
 extern crate serde_derive;
 
+extern crate edid;
+extern crate hex;
+extern crate indexmap;
+extern crate libc;
+extern crate regex;
 extern crate serde;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate toml;
 extern crate xdg;
 
+pub mod auto;
+pub mod condition;
+pub mod database;
+pub mod dpi;
+pub mod format;
+pub mod hooks;
+pub mod legacy;
+pub mod mode;
+pub mod modeline;
+pub mod monitor;
+pub mod others;
+pub mod placement;
+pub mod preset;
+pub mod refresh;
+pub mod rule;
+pub mod transform;
+pub mod yaml_edit;
+
+use std::cmp::Ordering;
+use std::convert::TryFrom;
 use std::io;
 use std::io::Read;
+use std::fmt;
+use std::ffi::OsString;
+use std::process;
 use std::process::Command;
-use std::process::Stdio;
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::fs::File;
-use std::collections::HashMap;
+use std::os::unix::io::AsRawFd;
+use std::collections::BTreeMap;
 use std::io::BufReader;
 use std::io::BufWriter;
 use std::io::prelude::*;
 
+use database::{ConfigSources, Database, LayeredDatabase};
+use dpi::DpiConfig;
+use hooks::Hooks;
+use format::Format;
+use mode::{Mode, Position};
+use modeline::{Modeline, Timing};
+use refresh::{Rate, Refresh};
+use monitor::MonitorInfo;
+use auto::Choice;
+use condition::{Conditions, Context, Lid};
+use placement::{Align, Side};
+use preset::PresetConfig;
+use others::Others;
+use transform::{Panning, Reflect, Transform, Transformation};
+use rule::{MatchRule, Role};
+
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
-    Json,
+    Json(serde_json::Error),
+    Yaml(serde_yaml::Error),
+    TomlDe(toml::de::Error),
+    TomlSer(toml::ser::Error),
     Xdg(xdg::BaseDirectoriesError),
+    Xrandr(String),
+    Xrdb(String),
+    UnknownProfile(String),
+    ProfileExists(String),
+    InvalidProfile(String),
+    ReadOnly(String),
+    Placement(String),
+    Preset(String),
+    Hook(String),
 }
 impl From<io::Error> for Error {
     fn from(x: io::Error) -> Self {
         Error::Io(x)
     }
 }
-//impl From<serde_json::Error> for Error {
-//    fn from(x: serde_json::Error) -> Self {
-//        Error::Json(x)
-//    }
-//}
+impl From<serde_json::Error> for Error {
+    fn from(x: serde_json::Error) -> Self {
+        Error::Json(x)
+    }
+}
+impl From<serde_yaml::Error> for Error {
+    fn from(x: serde_yaml::Error) -> Self {
+        Error::Yaml(x)
+    }
+}
+impl From<toml::de::Error> for Error {
+    fn from(x: toml::de::Error) -> Self {
+        Error::TomlDe(x)
+    }
+}
+impl From<toml::ser::Error> for Error {
+    fn from(x: toml::ser::Error) -> Self {
+        Error::TomlSer(x)
+    }
+}
 impl From<xdg::BaseDirectoriesError> for Error {
     fn from(x: xdg::BaseDirectoriesError) -> Self {
         Error::Xdg(x)
     }
 }
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref e) => write!(f, "{}", e),
+            Error::Json(ref e) => write!(f, "invalid json: {}", e),
+            Error::Yaml(ref e) => write!(f, "invalid yaml: {}", e),
+            Error::TomlDe(ref e) => write!(f, "invalid toml: {}", e),
+            Error::TomlSer(ref e) => write!(f, "toml: {}", e),
+            Error::Xdg(ref e) => write!(f, "{}", e),
+            Error::Xrandr(ref e) => write!(f, "xrandr failed: {}", e),
+            Error::Xrdb(ref e) => write!(f, "xrdb failed: {}", e),
+            Error::UnknownProfile(ref name) => write!(f, "unknown profile {}", name),
+            Error::ProfileExists(ref name) => write!(f, "profile {} already exists", name),
+            Error::InvalidProfile(ref e) => write!(f, "invalid profile: {}", e),
+            Error::ReadOnly(ref e) => write!(f, "{}, which is read-only", e),
+            Error::Placement(ref e) => write!(f, "cannot place monitors: {}", e),
+            Error::Preset(ref e) => write!(f, "cannot apply preset {}", e),
+            Error::Hook(ref e) => write!(f, "{}", e),
+        }
+    }
+}
 
 pub type DResult<T> = Result<T, Error>;
 
-#[derive(Hash, Ord, PartialOrd, Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+#[derive(Hash, Ord, PartialOrd, Eq, PartialEq, Clone, Debug, Default, Serialize, Deserialize)]
 pub enum Orientation {
+    #[default]
     Normal,
     Left,
     Inverted,
     Right,
 }
 
+impl Orientation {
+    /// Parses the xrandr name or the config file name of a rotation.
+    pub fn from_name(name: &str) -> Option<Orientation> {
+        match &name.to_lowercase()[..] {
+            "normal" => Some(Orientation::Normal),
+            "left" => Some(Orientation::Left),
+            "inverted" => Some(Orientation::Inverted),
+            "right" => Some(Orientation::Right),
+            _ => None,
+        }
+    }
+
+    /// The name xrandr uses for this rotation.
+    pub fn xrandr_name(&self) -> &'static str {
+        match *self {
+            Orientation::Normal => "normal",
+            Orientation::Inverted => "inverted",
+            Orientation::Left => "left",
+            Orientation::Right => "right",
+        }
+    }
+}
+
+/// How xrandr resamples a scaled output, see `--filter`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name {
+            "nearest" => Some(Filter::Nearest),
+            "bilinear" => Some(Filter::Bilinear),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Filter::Nearest => "nearest",
+            Filter::Bilinear => "bilinear",
+        }
+    }
+}
+
 #[derive(Hash, Ord, PartialOrd, Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct Geometry {
     pub width: usize,
@@ -58,280 +202,825 @@ pub struct Geometry {
     pub is_primary: bool,
 }
 
-#[derive(Hash, Ord, PartialOrd, Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+impl Geometry {
+    /// Size on screen of a picture of `size`, turned by `orientation`. Turning
+    /// back is the same swap, so this also gives the mode of a screen size.
+    pub fn rotate<T>(size: (T, T), orientation: &Orientation) -> (T, T) {
+        match *orientation {
+            Orientation::Left | Orientation::Right => (size.1, size.0),
+            Orientation::Normal | Orientation::Inverted => size,
+        }
+    }
+
+    /// Size of the mode shown, which is turned on screen.
+    pub fn mode_size(&self) -> (usize, usize) {
+        Geometry::rotate((self.width, self.height), &self.orientation)
+    }
+
+    pub fn position(&self) -> Position {
+        Position::new(self.x_offset as isize, self.y_offset as isize)
+    }
+}
+
+/// A mode listed by xrandr for a connected output.
+#[derive(PartialEq, Clone, Debug)]
+pub struct ModeInfo
+{
+    /// Name used with `--mode`, e.g. `1920x1200`.
+    pub name: String,
+    /// Mode id, e.g. `0x164`.
+    pub id: String,
+    pub width: usize,
+    pub height: usize,
+    /// Vertical refresh rate in Hz, 0 if xrandr did not list it.
+    pub refresh: f64,
+    pub preferred: bool,
+    pub current: bool,
+    /// The timing from the mode line and the `h:` and `v:` lines after it.
+    pub timing: Option<Modeline>,
+}
+
+impl ModeInfo {
+    pub fn area(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// The mode to write into a profile.
+    pub fn mode(&self) -> Mode {
+        self.name.parse().unwrap_or_else(|_| Mode::new(self.width, self.height))
+    }
+
+    /// Whether the mode runs at `rate`, as far as xrandr rounds it.
+    pub fn has_refresh(&self, rate: f64) -> bool {
+        (self.refresh - rate).abs() < 0.05
+    }
+}
+
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct Output
 {
+    /// Monitor name decoded from the EDID, see `MonitorInfo::model`.
+    #[serde(default)]
+    pub name: String,
     pub edid: String,
+    #[serde(default)]
     pub connection_name : String,
-    pub geometry: Option<Geometry>
+    pub geometry: Option<Geometry>,
+    /// Supported modes in the order xrandr lists them.
+    #[serde(skip)]
+    pub modes: Vec<ModeInfo>,
+    /// Physical width and height as reported by xrandr, e.g. `520mm x 320mm`.
+    #[serde(skip)]
+    pub physical_mm: Option<(u32, u32)>,
+    /// Reflection, transformation and panning while the output is on.
+    #[serde(skip)]
+    pub transformation: Transformation,
+}
+
+/// Connector name prefixes of built-in laptop panels.
+const INTERNAL_CONNECTORS: [&str; 3] = ["lvds", "edp", "dsi"];
+
+/// Largest diagonal of a built-in panel in cm, a bit more than 17 inches.
+const INTERNAL_MAX_DIAGONAL_CM: f64 = 45.0;
+
+fn is_internal_connector(connector: &str) -> bool {
+    let connector = connector.to_lowercase();
+    INTERNAL_CONNECTORS.iter().any(|x| connector.starts_with(x))
+}
+
+impl Output {
+    pub fn monitor(&self) -> Option<MonitorInfo> {
+        MonitorInfo::from_hex(&self.edid)
+    }
+
+    /// Physical width and height in mm, from xrandr or else the EDID.
+    pub fn physical_size_mm(&self) -> Option<(u32, u32)> {
+        self.physical_mm.or_else(|| {
+            self.monitor()
+                .filter(|x| x.width_cm > 0 && x.height_cm > 0)
+                .map(|x| (u32::from(x.width_cm) * 10, u32::from(x.height_cm) * 10))
+        })
+    }
+
+    /// Whether `selector` names this output by connector, model, serial or role.
+    pub fn selected_by(&self, selector: &str) -> bool {
+        self.connection_name == selector
+            || self.name == selector
+            || self.monitor().and_then(|x| x.serial).is_some_and(|x| x == selector)
+            || (selector == "internal" && self.is_internal())
+    }
+
+    /// The mode the monitor prefers, or the first one it supports.
+    pub fn preferred_mode(&self) -> Option<&ModeInfo> {
+        self.modes.iter().find(|x| x.preferred).or_else(|| self.modes.first())
+    }
+
+    /// The listed mode that comes closest to `mode` at `rate`: the mode
+    /// itself, else one of the same size at the closest refresh rate, else one
+    /// of the same aspect ratio and the closest area, else the preferred one.
+    ///
+    /// Without a rate, the fastest of the modes that are equally close is
+    /// taken.
+    pub fn nearest_mode(&self, mode: &Mode, rate: Option<f64>) -> Option<&ModeInfo> {
+        let name = mode.name();
+        let rate = rate.or(mode.refresh);
+        let by_refresh = |a: &ModeInfo, b: &ModeInfo| match rate {
+            Some(rate) => (b.refresh - rate).abs().partial_cmp(&(a.refresh - rate).abs()),
+            None => a.refresh.partial_cmp(&b.refresh),
+        }.unwrap_or(Ordering::Equal);
+
+        let exact = self.modes.iter().find(|m| m.name == name && rate.is_none_or(|rate| m.has_refresh(rate)));
+        let same_size = || self.modes
+            .iter()
+            .filter(|m| m.width == mode.width && m.height == mode.height)
+            .max_by(|a, b| (a.name == name).cmp(&(b.name == name)).then(by_refresh(a, b)));
+        let area = (mode.width * mode.height) as isize;
+        let same_aspect = || self.modes
+            .iter()
+            .filter(|m| m.width * mode.height == m.height * mode.width)
+            .max_by(|a, b| {
+                let distance = |m: &ModeInfo| (m.area() as isize - area).abs();
+                distance(b).cmp(&distance(a)).then(by_refresh(a, b))
+            });
+        exact.or_else(same_size).or_else(same_aspect).or_else(|| self.preferred_mode())
+    }
+
+    /// Whether the output is the built-in panel of a laptop.
+    ///
+    /// Besides the connector name, panels are recognized by an EDID without
+    /// serial number and a small physical size.
+    pub fn is_internal(&self) -> bool {
+        if is_internal_connector(&self.connection_name) {
+            return true;
+        }
+        match self.monitor() {
+            Some(monitor) if monitor.serial.is_none() && monitor.width_cm > 0 && monitor.height_cm > 0 => {
+                let (w, h) = (f64::from(monitor.width_cm), f64::from(monitor.height_cm));
+                (w * w + h * h).sqrt() <= INTERNAL_MAX_DIAGONAL_CM
+            }
+            _ => false,
+        }
+    }
 }
 
 pub type RawXrandr = String;
-pub type Profiles = HashMap<String, Profile>;
-pub type ConnectedOutputs = HashMap<String, Output>;
-pub type OutputsRawXrandr = HashMap<String, RawXrandr>;
+pub type RawProfiles = BTreeMap<String, RawProfile>;
+pub type ConnectedOutputs = Vec<Output>;
+pub type OutputsRawXrandr = BTreeMap<String, RawXrandr>;
 
+/// A profile of the old json config file, made of raw xrandr arguments.
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Profile
+pub struct RawProfile
 {
     pub outputs: OutputsRawXrandr,
+    /// Arguments for all other outputs, see `legacy::parse_other_args`.
     pub other_outputs: RawXrandr,
 }
 
+/// The old json config file.
 #[derive(Clone, Debug, Serialize, Deserialize, Default)]
 pub struct ConfigFile {
-    pub autoprofiles: Vec<ConnectedOutputs>,
-    pub profiles: Profiles,
+    pub autoprofiles: Vec<BTreeMap<String, Output>>,
+    pub profiles: RawProfiles,
 }
 
 pub type OutputNames = Vec<String>;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
-struct MonitorConfig
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MonitorConfig
 {
     pub display_name : String,
-    pub mode : String,
-    pub position : String,
+    /// Hex encoded EDID of the monitor, used to tell identical models apart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edid : Option<String>,
+    /// The port the monitor was connected to when the layout was saved.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connector : Option<String>,
+    /// Matches any monitor the rule accepts, instead of the one with this EDID.
+    #[serde(rename = "match", default, skip_serializing_if = "Option::is_none")]
+    pub rule : Option<MatchRule>,
+    /// Matches the monitor by what it is used for, like `internal` or `external[0]`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub role : Option<Role>,
+    /// The mode to show, the preferred one of the monitor if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode : Option<Mode>,
+    /// Refresh rate in Hz, the best one for the mode if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate : Option<f64>,
+    /// How to choose the refresh rate, instead of the one of the profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh : Option<Refresh>,
+    /// Timing to create the mode with if the monitor does not list it, see
+    /// `modeline`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modeline : Option<Timing>,
+    /// Exact timing of the mode when the layout was saved, to tell it from
+    /// other modes of the same name.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timing : Option<Modeline>,
+    /// Part of the screen to scale to the mode as `"WxH"`, see `--scale-from`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale_from : Option<String>,
+    /// Factor the screen area of the monitor is larger than its mode, see
+    /// `--scale`. Above 1 makes everything smaller.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scale : Option<f64>,
+    /// Resampling of a scaled picture.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub filter : Option<Filter>,
+    /// Mirrors the picture along the x axis, the y axis or both.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reflect : Option<Reflect>,
+    /// Matrix transforming the picture, see `--transform`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transform : Option<Transform>,
+    /// Area larger than the mode to pan across, see `--panning`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub panning : Option<Panning>,
+    /// Output properties to set, like `Broadcast RGB: Full`, see `--set`.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub properties : BTreeMap<String, String>,
+    /// Absolute position, unused with relative placement.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position : Option<Position>,
+    /// Monitor to place this one next to, by connector, display name or serial.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub right_of : Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub left_of : Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub above : Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub below : Option<String>,
+    /// Monitor whose picture this one shows, see `--same-as`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub same_as : Option<String>,
+    /// Edge shared with the monitor placed against, top or left if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub align : Option<Align>,
+    /// Shift along the shared edge, in pixels.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub offset : isize,
     pub orientation : Orientation,
-    pub primary : bool
+    pub primary : bool,
+    /// Disabled monitors are turned off even when connected.
+    #[serde(default = "default_true", skip_serializing_if = "is_true")]
+    pub enabled : bool,
 }
 
-pub struct ConfigAndXrandr
-{
-    pub connected_outputs: ConnectedOutputs,
-    pub output_names: OutputNames,
+fn default_true() -> bool {
+    true
 }
 
-/*
-impl Output {
-    pub fn raw_edid_to_bytes(&self) -> Vec<u8> {
-        let mut gather_bytes = Vec::new();
+fn is_true(x: &bool) -> bool {
+    *x
+}
 
-        for hex_byte in self.edid
-            .as_bytes()
-            .chunks(2)
-            .map(|b| std::str::from_utf8(b).unwrap())
-        {
-            let byte = u8::from_str_radix(hex_byte, 16).unwrap();
+fn is_zero(x: &isize) -> bool {
+    *x == 0
+}
 
-            //println!("{}: {}", hex_byte, byte);
-            gather_bytes.push(byte);
+impl MonitorConfig {
+    /// Whether this config describes the monitor connected as `output`.
+    pub fn matches(&self, output: &Output) -> bool {
+        if let Some(ref rule) = self.rule {
+            return rule.matches(output);
+        }
+        match self.edid {
+            Some(ref edid) if !output.edid.is_empty() => *edid == output.edid,
+            _ => self.display_name == output.name,
         }
-        //println!("BYTES: {:?}", gather_bytes);
-        gather_bytes
     }
 
-    /*
-    pub fn parse_edid(&self) -> EDID {
-        edid::parse(&self.raw_edid_to_bytes()).unwrap().1
+    /// Whether this config describes `output`, the `external_index`-th
+    /// external monitor if it is not internal.
+    pub fn accepts(&self, output: &Output, external_index: Option<usize>) -> bool {
+        match self.role {
+            Some(role) => role.accepts(output, external_index) && self.rule.as_ref().is_none_or(|x| x.matches(output)),
+            None => self.matches(output),
+        }
     }
-    */
-}
-*/
 
-pub fn query_xrandr() -> io::Result<String>
-{
-    //let mut s = String::new();
-    //child.stdout.take().unwrap().read_to_string(&mut s)?;
+    /// Whether `selector` names this monitor by connector, display name,
+    /// serial or role.
+    pub fn selected_by(&self, selector: &str) -> bool {
+        self.connector.as_ref().is_some_and(|x| x == selector)
+            || self.display_name == selector
+            || self.monitor().and_then(|x| x.serial).is_some_and(|x| x == selector)
+            || self.role.is_some_and(|x| x.to_string() == selector)
+            || (selector == "internal" && self.role.is_none()
+                && self.connector.as_ref().is_some_and(|x| is_internal_connector(x)))
+    }
 
-    let monitor_info_cli = String::from_utf8(
-        Command::new("xrandr")
-            .args(&["--prop"])
-            .output()
-            .unwrap()
-            .stdout,
-    ).unwrap();
+    /// Whether both configs describe the same physical monitor.
+    pub fn same_monitor(&self, other: &MonitorConfig) -> bool {
+        match (&self.edid, &other.edid) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.display_name == other.display_name && self.connector == other.connector,
+        }
+    }
 
-    return Ok(monitor_info_cli);
+    pub fn monitor(&self) -> Option<MonitorInfo> {
+        self.edid.as_ref().and_then(|x| MonitorInfo::from_hex(x))
+    }
 }
 
-pub fn invoke_xrandr(args: &[String]) -> io::Result<()> {
-    let mut child = Command::new("xrandr")
-        .args(args)
-        .spawn()?;
+/// Changes individual fields of the monitors inherited from a parent profile.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OutputOverride
+{
+    /// Selects the monitors to change by connector, display name or serial.
+    pub output : String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode : Option<Mode>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate : Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh : Option<Refresh>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub position : Option<Position>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orientation : Option<Orientation>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub primary : Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled : Option<bool>,
+}
 
-    let ecode = child.wait()?;
-    assert!(ecode.success());
+impl OutputOverride {
+    pub fn matches(&self, config: &MonitorConfig) -> bool {
+        config.selected_by(&self.output)
+    }
 
-    return Ok(());
+    pub fn apply(&self, config: &mut MonitorConfig) {
+        if let Some(ref mode) = self.mode {
+            config.mode = Some(mode.clone());
+        }
+        if let Some(rate) = self.rate {
+            config.rate = Some(rate);
+        }
+        if let Some(refresh) = self.refresh {
+            config.refresh = Some(refresh);
+        }
+        if let Some(position) = self.position {
+            config.clear_placement();
+            config.position = Some(position);
+        }
+        if let Some(ref orientation) = self.orientation {
+            config.orientation = orientation.clone();
+        }
+        if let Some(primary) = self.primary {
+            config.primary = primary;
+        }
+        if let Some(enabled) = self.enabled {
+            config.enabled = enabled;
+        }
+    }
 }
 
-pub fn parse_xrandr(s: &str) -> (ConnectedOutputs, OutputNames)
+/// A named display layout, as stored in the profile database or a layout file.
+#[derive(Clone, Debug, Serialize, Deserialize, Default)]
+pub struct Profile
 {
-    let mut connected_outputs = HashMap::new();
-    let mut output_names = Vec::new();
-
-    let mut lines = s.lines();
-    let mut line;
+    /// Name of a profile whose monitors this one starts out with.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+    /// Breaks ties between profiles that fit the connected monitors equally well.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub priority: isize,
+    /// Conditions under which `--auto` may pick the profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<Conditions>,
+    /// Commands to run around applying the profile.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hooks: Option<Hooks>,
+    /// Overrides the global DPI settings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dpi: Option<DpiConfig>,
+    /// Monitors to make primary, by connector, display name, serial or
+    /// `internal`, in case the one marked primary is not turned on.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub primary_order: Vec<String>,
+    /// What to do with connected monitors the profile does not list, turn
+    /// them off if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub others: Option<Others>,
+    /// How to choose the refresh rates, `saved` if not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub refresh: Option<Refresh>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<MonitorConfig>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub overrides: Vec<OutputOverride>,
+}
 
-    macro_rules! unwrap_or_break {
-        ($e:expr) => (
-            if let Some(e) = $e {
-                e
-            } else {
-                break;
-            }
-        )
+fn parse_bool(value: &str) -> DResult<bool> {
+    match value {
+        "true" | "yes" | "on" => Ok(true),
+        "false" | "no" | "off" => Ok(false),
+        _ => Err(Error::InvalidProfile(format!("expected true or false, got {}", value))),
     }
+}
 
-    macro_rules! next_line {
-        ($line:expr, $lines:expr) => (
-            if let Some(line) = $lines.next() {
-                $line = line;
+impl Profile {
+    /// Changes a single setting, given as `extends` or `<output>.<field>`.
+    ///
+    /// The output is selected like in overrides, by connector, display name
+    /// or serial number.
+    pub fn set(&mut self, key: &str, value: &str) -> DResult<()> {
+        if key == "extends" {
+            self.extends = if value.is_empty() { None } else { Some(value.to_string()) };
+            return Ok(());
+        }
+        if key == "priority" {
+            self.priority = value.parse()
+                .map_err(|_| Error::InvalidProfile(format!("expected a number, got {}", value)))?;
+            return Ok(());
+        }
+        if key == "primary_order" {
+            self.primary_order = value.split(',').map(str::trim).filter(|x| !x.is_empty()).map(String::from).collect();
+            return Ok(());
+        }
+        if key == "others" {
+            self.others = if value.is_empty() {
+                None
             } else {
-                break;
+                Some(Others::from_name(value).ok_or_else(|| Error::InvalidProfile(format!("unknown policy {}", value)))?)
+            };
+            return Ok(());
+        }
+        if key == "refresh" {
+            self.refresh = if value.is_empty() { None } else { Some(value.parse().map_err(Error::InvalidProfile)?) };
+            return Ok(());
+        }
+        if key == "dpi" {
+            let dpi = self.dpi.get_or_insert_with(DpiConfig::default);
+            dpi.value = if value.is_empty() {
+                None
+            } else {
+                Some(value.parse().map_err(|_| Error::InvalidProfile(format!("expected a number, got {}", value)))?)
+            };
+            return Ok(());
+        }
+
+        let (selector, field) = key
+            .rsplit_once('.')
+            .ok_or_else(|| Error::InvalidProfile(format!("unknown setting {}", key)))?;
+
+        let mut found = false;
+        for config in self.outputs.iter_mut().filter(|x| x.selected_by(selector)) {
+            match field {
+                "mode" if value.is_empty() => {
+                    config.mode = None;
+                    config.timing = None;
+                }
+                "mode" => {
+                    config.mode = Some(value.parse().map_err(Error::InvalidProfile)?);
+                    config.timing = None;
+                }
+                "rate" if value.is_empty() => config.rate = None,
+                "rate" => {
+                    config.rate = Some(value.parse()
+                        .map_err(|_| Error::InvalidProfile(format!("expected a number, got {}", value)))?)
+                }
+                "refresh" if value.is_empty() => config.refresh = None,
+                "refresh" => config.refresh = Some(value.parse().map_err(Error::InvalidProfile)?),
+                "modeline" if value.is_empty() => config.modeline = None,
+                "modeline" => config.modeline = Some(value.parse().map_err(Error::InvalidProfile)?),
+                "scale_from" if value.is_empty() => config.scale_from = None,
+                "scale_from" => config.scale_from = Some(value.to_string()),
+                "scale" if value.is_empty() => config.scale = None,
+                "scale" => {
+                    config.scale = Some(value.parse().ok().filter(|&x: &f64| x > 0.0)
+                        .ok_or_else(|| Error::InvalidProfile(format!("expected a positive number, got {}", value)))?)
+                }
+                "filter" if value.is_empty() => config.filter = None,
+                "filter" => {
+                    config.filter = Some(Filter::from_name(value)
+                        .ok_or_else(|| Error::InvalidProfile(format!("unknown filter {}", value)))?)
+                }
+                "reflect" if value.is_empty() => config.reflect = None,
+                "reflect" => {
+                    config.reflect = Some(Reflect::from_name(value)
+                        .ok_or_else(|| Error::InvalidProfile(format!("unknown reflection {}", value)))?)
+                }
+                "transform" if value.is_empty() => config.transform = None,
+                "transform" => config.transform = Some(Transform::try_from(value.to_string()).map_err(Error::InvalidProfile)?),
+                "panning" if value.is_empty() => config.panning = None,
+                "panning" => config.panning = Some(Panning::try_from(value.to_string()).map_err(Error::InvalidProfile)?),
+                "position" => {
+                    let position = value.parse().map_err(Error::InvalidProfile)?;
+                    config.clear_placement();
+                    config.position = Some(position);
+                }
+                "align" if value.is_empty() => config.align = None,
+                "align" => {
+                    config.align = Some(Align::from_name(value)
+                        .ok_or_else(|| Error::InvalidProfile(format!("unknown alignment {}", value)))?)
+                }
+                "offset" => {
+                    config.offset = value.parse()
+                        .map_err(|_| Error::InvalidProfile(format!("expected a number, got {}", value)))?
+                }
+                _ if Side::from_name(field).is_some() => config.place(Side::from_name(field).unwrap(), value),
+                "orientation" => {
+                    config.orientation = Orientation::from_name(value)
+                        .ok_or_else(|| Error::InvalidProfile(format!("unknown orientation {}", value)))?
+                }
+                "primary" => config.primary = parse_bool(value)?,
+                "enabled" => config.enabled = parse_bool(value)?,
+                _ => return Err(Error::InvalidProfile(format!("unknown output setting {}", field))),
             }
-        )
-    }
+            found = true;
+        }
 
-    // Parse away "Screen N" headers
-    loop {
-        line = lines.next().expect("Expected Displays");
-        if !line.starts_with("Screen") {
-            break;
+        if !found {
+            return Err(Error::InvalidProfile(format!("no monitor {} in profile", selector)));
         }
+        Ok(())
     }
+}
 
-    // Parse Displays
-    loop {
-        if line.starts_with(char::is_whitespace) {
-            next_line!(line, lines);
-            continue;
-        }
+pub struct ConfigAndXrandr
+{
+    pub connected_outputs: ConnectedOutputs,
+    pub output_names: OutputNames,
+}
 
-        let mut splited = line.split_whitespace();
-        let output_name = unwrap_or_break!(splited.next());
-        let state = unwrap_or_break!(splited.next());
+/// Where an enabled output ends up once a plan is applied.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PlannedOutput
+{
+    pub output: String,
+    pub x: isize,
+    pub y: isize,
+    /// Size on screen, after rotation.
+    pub width: isize,
+    pub height: isize,
+    pub rotate: String,
+    pub primary: bool,
+}
 
-        if state.ends_with("connected") && !output_name.starts_with("VIRTUAL") {
-            output_names.push(output_name.to_string());
-        }
+/// The xrandr invocations needed to apply a profile.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Plan
+{
+    pub commands: Vec<Vec<String>>,
+    /// How the profile was fitted to the monitors, like modes that are
+    /// not available and were replaced.
+    pub notes: Vec<String>,
+    /// The outputs that are enabled afterwards.
+    pub outputs: Vec<PlannedOutput>,
+}
 
-        if state != "connected" || output_name.starts_with("VIRTUAL") {
-            next_line!(line, lines);
-            continue;
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for note in &self.notes {
+            writeln!(f, "# {}", note)?;
         }
-
-        let mut next = unwrap_or_break!(splited.next());
-        let is_primary = next == "primary";
-        if is_primary {
-            next = splited.next().unwrap();
+        for command in &self.commands {
+            writeln!(f, "xrandr {}", command.join(" "))?;
         }
+        Ok(())
+    }
+}
 
-        let is_part_of_desktop = next != "(normal";
+pub fn query_xrandr() -> io::Result<String>
+{
+    let output = Command::new("xrandr")
+        .args(["--verbose"])
+        .output()?;
 
-        let mut geometry = None;
-        if is_part_of_desktop {
-            // parse current screen config and orientation
-            let resolution = next;
+    if !output.status.success() {
+        return Err(io::Error::other(String::from_utf8_lossy(&output.stderr).into_owned()));
+    }
 
-            let mut iter = resolution.split(&['x', '+'][..]);
-            let width = iter.next().unwrap().parse().unwrap();
-            let height = iter.next().unwrap().parse().unwrap();
-            let x_offset = iter.next().unwrap().parse().unwrap();
-            let y_offset = iter.next().unwrap().parse().unwrap();
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
 
-            let _unknown_hex_id = splited.next().unwrap();
+pub fn invoke_xrandr(args: &[String]) -> DResult<()> {
+    let mut child = Command::new("xrandr")
+        .args(args)
+        .spawn()?;
 
-            let orientation = if _unknown_hex_id.contains(")") {   splited.next().unwrap() } else {_unknown_hex_id};//.replace("(", "").replace(")", "");
-            let orientation = match &orientation[..] {
-                "normal" => Orientation::Normal,
-                "left" => Orientation::Left,
-                "inverted" => Orientation::Inverted,
-                "right" => Orientation::Right,
-                _ => Orientation::Normal,
-            };
+    let ecode = child.wait()?;
+    if !ecode.success() {
+        return Err(Error::Xrandr(format!("xrandr {} exited with {}", args.join(" "), ecode)));
+    }
 
-            geometry = Some(Geometry {
-                orientation,
-                width,
-                height,
-                x_offset,
-                y_offset,
-                is_primary,
-            });
-        }
+    Ok(())
+}
 
-        loop {
-            next_line!(line, lines);
+fn is_hex_line(line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty() && line.chars().all(|c| c.is_ascii_hexdigit())
+}
 
-            assert!(line.starts_with(char::is_whitespace),
-                    "no EDID Data found for connected device {}!",
-                    output_name);
+/// Splits `xrandr --prop` output into the header line of each output and
+/// the indented lines that belong to it.
+fn output_blocks(s: &str) -> Vec<(&str, Vec<&str>)> {
+    let mut blocks: Vec<(&str, Vec<&str>)> = Vec::new();
 
-            if line.trim() == "EDID:" {
-                let mut gather = String::new();
-                for _ in 0..16 {
-                    gather.push_str(lines.next().unwrap().trim());
-                }
+    for line in s.lines() {
+        if line.trim().is_empty() || line.starts_with("Screen") {
+            continue;
+        }
+        if line.starts_with(char::is_whitespace) {
+            if let Some(block) = blocks.last_mut() {
+                block.1.push(line);
+            }
+        } else {
+            blocks.push((line, Vec::new()));
+        }
+    }
 
-                assert_eq!(gather.len(), 256 * 2);
+    blocks
+}
 
-                let data = hex::decode(&gather).expect("Decoding failed");
+fn parse_geometry(header: &[&str], is_primary: bool) -> Option<Geometry> {
+    // Disabled outputs go straight to the list of supported rotations
+    let resolution = *header.first()?;
+    if resolution.starts_with('(') {
+        return None;
+    }
 
-                let (mon_nfo, llll) =  edid::parse(data.as_ref()).unwrap();
+    let mut iter = resolution.split(&['x', '+'][..]).map(|x| x.parse());
+    let width = iter.next()?.ok()?;
+    let height = iter.next()?.ok()?;
+    let x_offset = iter.next()?.ok()?;
+    let y_offset = iter.next()?.ok()?;
 
+    // The resolution is followed by the mode id, e.g. "(0x142)", and then
+    // the rotation, unless it is omitted for a normal rotation.
+    let orientation = header[1..]
+        .iter()
+        .find(|x| !x.starts_with("(0x"))
+        .and_then(|x| Orientation::from_name(x))
+        .unwrap_or(Orientation::Normal);
+
+    Some(Geometry {
+        orientation,
+        width,
+        height,
+        x_offset,
+        y_offset,
+        is_primary,
+    })
+}
 
-                let name = llll.descriptors.iter().find_map(|x| match x
-                {
-                    edid::Descriptor::ProductName(v) => Some(v),
-                    _ => None
-                }).unwrap();
+/// Reads the trailing `520mm x 320mm` of an output header, unless it is zero.
+fn parse_physical_size(header: &[&str]) -> Option<(u32, u32)> {
+    match *header {
+        [.., width, "x", height] => {
+            let width = width.strip_suffix("mm")?.parse().ok()?;
+            let height = height.strip_suffix("mm")?.parse().ok()?;
+            if width > 0 && height > 0 { Some((width, height)) } else { None }
+        }
+        _ => None,
+    }
+}
 
-                let serial = llll.descriptors.iter().find_map(|x| match x
-                {
-                    edid::Descriptor::SerialNumber(v) => Some(v),
-                    _ => None
-                });
+/// Parses a mode line like `1920x1200 (0x164) 154.000MHz +HSync +VSync *current +preferred`.
+fn parse_mode_line(line: &str) -> Option<ModeInfo> {
+    let mut parts = line.split_whitespace();
+    let name = parts.next()?;
+    let id = parts.next()?;
+    if !id.starts_with("(0x") {
+        return None;
+    }
 
-                println!("{:?}", serial);
-
-                /*
-                let mut monitor_name = gather.split_off(190);
-                let mon_name_term = monitor_name.find("0a").unwrap();
-                monitor_name.split_off(mon_name_term);
-                let decoded_mon_name = hex::decode(monitor_name).expect("Decoding failed");
-                let mon_name = match String::from_utf8(decoded_mon_name)
-                {
-                  Ok(x) => x,
-                    Err(e) => "lol".to_string()
-                };
+    // Created modes are named like 2560x1440_75.00
+    let Mode { width, height, .. } = name.parse().ok()?;
+    let flags: Vec<&str> = parts.collect();
+
+    // The rest of the timing follows on the h: and v: lines
+    let clock = flags.first().and_then(|x| x.strip_suffix("MHz")).and_then(|x| x.parse().ok());
+    let timing = clock.map(|clock| Modeline {
+        clock,
+        horizontal: [width, 0, 0, 0],
+        vertical: [height, 0, 0, 0],
+        flags: flags[1..]
+            .iter()
+            .filter(|x| !matches!(**x, "*current" | "+preferred"))
+            .map(|x| x.to_string())
+            .collect(),
+    });
+
+    Some(ModeInfo {
+        name: name.to_string(),
+        id: id.trim_matches(&['(', ')'][..]).to_string(),
+        width,
+        height,
+        refresh: 0.0,
+        preferred: flags.contains(&"+preferred"),
+        current: flags.contains(&"*current"),
+        timing,
+    })
+}
 
-                 */
+/// Reads a line like `h: width  1920 start 1968 end 2000 total 2080 skew 0 clock 74.04KHz`
+/// as the size, sync start, sync end and total.
+fn parse_timing_line(line: &str) -> Option<[usize; 4]> {
+    let words: Vec<&str> = line.split_whitespace().collect();
+    let value = |key: &str| words.iter().position(|x| *x == key).and_then(|i| words.get(i + 1)?.parse().ok());
+    Some([value(words.get(1)?)?, value("start")?, value("end")?, value("total")?])
+}
 
-                let out = Output
-                {
-                    edid: gather,
-                    connection_name : output_name.to_string(),
-                    geometry,
-                };
+/// Reads the refresh rate from the `v: height ... clock 59.95Hz` line after a mode.
+fn parse_refresh(line: &str) -> Option<f64> {
+    line.split_whitespace().last()?.trim_end_matches("Hz").parse().ok()
+}
 
-                //println!("HEX: {}", out.edid);
-                //println!("PARSED: {:?}", out.parse_edid());
+pub fn parse_xrandr(s: &str) -> (ConnectedOutputs, OutputNames)
+{
+    let mut connected_outputs = Vec::new();
+    let mut output_names = Vec::new();
 
-                connected_outputs.insert(name.to_string(), out);
+    for (header, body) in output_blocks(s) {
+        let splited: Vec<&str> = header.split_whitespace().collect();
+        if splited.len() < 2 {
+            continue;
+        }
+        let output_name = splited[0];
+        let state = splited[1];
 
-                break;
+        if output_name.starts_with("VIRTUAL") || !state.ends_with("connected") {
+            continue;
+        }
+        output_names.push(output_name.to_string());
+
+        if state != "connected" {
+            continue;
+        }
+
+        let is_primary = splited.get(2) == Some(&"primary");
+        let rest = if is_primary { &splited[3..] } else { &splited[2..] };
+        let geometry = parse_geometry(rest, is_primary);
+        let physical_mm = parse_physical_size(rest);
+        let (reflect, panning) = transform::parse_header(rest);
+        let mut transformation = Transformation { reflect, panning, ..Transformation::default() };
+
+        let mut edid = String::new();
+        let mut modes: Vec<ModeInfo> = Vec::new();
+        let mut lines = body.iter().peekable();
+        while let Some(line) = lines.next() {
+            if line.trim() == "EDID:" {
+                while let Some(line) = lines.next_if(|x| is_hex_line(x)) {
+                    edid.push_str(line.trim());
+                }
+            } else if let Some(first) = line.trim_start().strip_prefix("Transform:") {
+                // Three rows of three numbers, the first one after the label
+                let mut values: Vec<f64> = first.split_whitespace().filter_map(|x| x.parse().ok()).collect();
+                for _ in 0..2 {
+                    if let Some(row) = lines.next() {
+                        values.extend(row.split_whitespace().filter_map(|x| x.parse::<f64>().ok()));
+                    }
+                }
+                transformation.transform = Transform::from_values(&values).filter(|x| !x.is_identity());
+            } else if let Some(name) = line.trim_start().strip_prefix("filter:") {
+                transformation.filter = Filter::from_name(name.trim());
+            } else if let Some(mode) = parse_mode_line(line) {
+                modes.push(mode);
+            } else if line.trim_start().starts_with("h:") {
+                if let Some(timing) = modes.last_mut().and_then(|x| x.timing.as_mut()) {
+                    timing.horizontal = parse_timing_line(line).unwrap_or_default();
+                }
+            } else if line.trim_start().starts_with("v:") {
+                if let Some(mode) = modes.last_mut() {
+                    mode.refresh = parse_refresh(line).unwrap_or(0.0);
+                    if let Some(ref mut timing) = mode.timing {
+                        timing.vertical = parse_timing_line(line).unwrap_or_default();
+                    }
+                }
             }
         }
+        for mode in &mut modes {
+            mode.timing = mode.timing.take().filter(|x| x.horizontal[3] > 0 && x.vertical[3] > 0);
+        }
 
+        let name = MonitorInfo::from_hex(&edid)
+            .map(|x| x.model)
+            .unwrap_or_else(|| output_name.to_string());
+
+        connected_outputs.push(Output {
+            name,
+            edid,
+            connection_name: output_name.to_string(),
+            geometry,
+            modes,
+            physical_mm,
+            transformation,
+        });
     }
 
     output_names.sort();
+    connected_outputs.sort_by(|a, b| a.connection_name.cmp(&b.connection_name));
     (connected_outputs, output_names)
 }
 
-
 pub fn load_xrandr_layout() -> DResult<ConfigAndXrandr>
 {
-    //let config_file = {
-    //    use std::thread;
-    //    let path = path.to_owned();
-    //    thread::spawn(move || parse_json(&load_file(&path)?))
-    //};
-
-    //let config_file = config_file.join().unwrap()?;
-
     let (connected_outputs, output_names) = parse_xrandr(&query_xrandr()?);
 
     Ok(ConfigAndXrandr
@@ -341,144 +1030,432 @@ pub fn load_xrandr_layout() -> DResult<ConfigAndXrandr>
     })
 }
 
+/// Builds a profile from the currently enabled outputs.
+pub fn capture_profile(curr_layout: &ConfigAndXrandr) -> Profile
+{
+    let mut outputs: Vec<MonitorConfig> = curr_layout.connected_outputs.iter().filter_map(capture_output).collect();
+
+    // Left to right, so saving the same layout twice gives the same file
+    outputs.sort_by_key(|x| (x.position, x.connector.clone()));
+
+    Profile { outputs, ..Profile::default() }
+}
 
-pub fn save_layout(path : &str)
+/// Describes what an output shows now, if it is turned on.
+pub fn capture_output(x: &Output) -> Option<MonitorConfig>
 {
-    let mut file = match File::create(path)
-    {
-        Ok(x) => x,
-        Err(e) => { eprintln!("Error opening file: {}", e); return;}
+    let geo = x.geometry.as_ref()?;
+
+    // The geometry is the size on screen, which differs from the mode
+    // once the output is scaled
+    let current_mode = x.modes.iter().find(|m| m.current);
+    let mode = match current_mode {
+        Some(m) => m.mode(),
+        None => {
+            let (width, height) = geo.mode_size();
+            Mode::new(width, height)
+        }
     };
+    // The timing is only needed when the name does not tell the mode
+    let timing = current_mode
+        .filter(|m| x.modes.iter().filter(|n| n.name == m.name).count() > 1)
+        .and_then(|m| m.timing.clone());
+
+    let current = &x.transformation;
+    let mut scale = None;
+    let mut scale_from = None;
+    let mut transform = None;
+    match current.transform.map(|t| (t, t.as_scale())) {
+        Some((_, Some((sx, sy)))) if (sx - sy).abs() < 1e-6 => scale = Some(sx),
+        Some((_, Some((sx, sy)))) => {
+            let (w, h) = Geometry::rotate(mode.size(), &geo.orientation);
+            scale_from = Some(format!("{}x{}", (w as f64 * sx).round(), (h as f64 * sy).round()));
+        }
+        Some((t, None)) => transform = Some(t),
+        None => (),
+    }
 
-    let curr_layout = match load_xrandr_layout()
-    {
-        Ok(x) => x,
-        Err(e) => {eprintln!("Error reading xrandr config: {:?}", e); return;}
+    // The output moves across the panning area, which stays in place
+    let position = match current.panning {
+        Some(ref panning) => Position::new(panning.area.x, panning.area.y),
+        None => geo.position(),
     };
 
-    //let curr_configs : Vec<MonitorConfig>
-    let curr_configs : Vec<MonitorConfig> = curr_layout.connected_outputs
-    .into_iter()
-    .map(|x|
+    Some(MonitorConfig
     {
-        if x.1.geometry.is_none(){ return None }
+        display_name : x.name.clone(),
+        edid : if x.edid.is_empty() { None } else { Some(x.edid.clone()) },
+        connector : Some(x.connection_name.clone()),
+        mode : Some(mode),
+        timing,
+        scale,
+        scale_from,
+        filter : current.filter.filter(|_| current.transform.is_some()),
+        reflect : current.reflect,
+        transform,
+        panning : current.panning,
+        orientation : geo.orientation.clone(),
+        position : Some(position),
+        primary : geo.is_primary,
+        enabled : true,
+        ..MonitorConfig::default()
+    })
+}
 
-        let geo = x.1.geometry.unwrap();
+/// Pairs every monitor of the profile with the connected output showing it.
+///
+/// Each connected output is used at most once, so two monitors of the same
+/// model are not both mapped to the same port.
+pub fn match_outputs<'a>(profile: &'a Profile, connected: &'a [Output]) -> Vec<(&'a MonitorConfig, &'a Output)>
+{
+    let mut used = vec![false; connected.len()];
+    let mut matched = Vec::new();
 
-        let mode = if geo.orientation == Orientation::Normal || geo.orientation == Orientation::Inverted
-        {
-            format!("{}x{}", geo.width, geo.height)
+    let mut externals = 0;
+    let external_index: Vec<Option<usize>> = connected
+        .iter()
+        .map(|x| if x.is_internal() { None } else { externals += 1; Some(externals - 1) })
+        .collect();
+
+    for config in &profile.outputs {
+        // Prefer the port the monitor was saved on if the name is ambiguous
+        let candidates: Vec<usize> = (0..connected.len())
+            .filter(|&i| !used[i] && config.accepts(&connected[i], external_index[i]))
+            .collect();
+        let pick = candidates
+            .iter()
+            .find(|&&i| config.connector.as_ref() == Some(&connected[i].connection_name))
+            .or_else(|| candidates.first());
+
+        if let Some(&i) = pick {
+            used[i] = true;
+            matched.push((config, &connected[i]));
         }
-        else
-        {
-            format!("{}x{}", geo.height, geo.width)
-        };
-
-
-        return Some(MonitorConfig
-        {
-            display_name : x.0,
-            mode,
-            orientation : geo.orientation,
-            position : format!("{}x{}", geo.x_offset, geo.y_offset),
-            primary : geo.is_primary
-        })
-    }).filter(|x| x.is_some())
-      .map(|x| x.unwrap())
-      .collect();
+    }
 
-    let yaml_file = serde_yaml::to_string(&curr_configs).unwrap();
-    file.write_all(yaml_file.as_bytes());
-    file.flush();
+    matched
 }
 
-pub fn load_layout(path : &str)
+/// Computes the xrandr calls that switch from the current state to `profile`.
+pub fn plan_profile(profile: &Profile, curr_layout: &ConfigAndXrandr) -> DResult<Plan>
 {
-    let mut file = match File::open(path)
-    {
-        Ok(x) => x,
-        Err(e) => { eprintln!("Error opening file: {}", e); return;}
-    };
+    let matched = match_outputs(profile, &curr_layout.connected_outputs);
+    let monitor_to_enable : Vec<_> = matched.iter().cloned().filter(|x| x.0.enabled).collect();
 
-    let mut configs : Vec<MonitorConfig> = serde_yaml::from_reader(file).unwrap();
-
-    let curr_layout = match load_xrandr_layout()
-    {
-        Ok(x) => x,
-        Err(e) => {eprintln!("Error reading xrandr config: {:?}", e); return;}
-    };
+    // Connected monitors the profile does not list
+    let policy = profile.others.unwrap_or_default();
+    let others : Vec<&Output> = curr_layout.connected_outputs
+        .iter()
+        .filter(|x| !matched.iter().any(|y| std::ptr::eq(y.1, *x)))
+        .collect();
 
-    let ports_to_enable : Vec<String> = curr_layout.connected_outputs.iter().filter(|x| configs.iter().any(|y| y.display_name == *(*x).0)).map(|x| x.1.connection_name.clone()).collect();
-    let monitor_to_enable : Vec<(String, Output)> = curr_layout.connected_outputs.into_iter().filter(|x| configs.iter().any(|y| y.display_name == *(*x).0)).map(|x| ( x.0, x.1)).collect();
+    let ports_to_disable : Vec<&String> = curr_layout.output_names
+        .iter()
+        .filter(|x| !monitor_to_enable.iter().any(|y| y.1.connection_name == **x))
+        .filter(|x| policy == Others::Off || !others.iter().any(|y| y.connection_name == **x))
+        .collect();
 
-    println!("{:?}", curr_layout.output_names);
-    let ports_to_disable : Vec<String> = curr_layout.output_names.into_iter().filter(|x| !ports_to_enable.iter().any(|y| y == x)).collect();
-    println!("{:?}", ports_to_enable);
-    println!("{:?}", ports_to_disable);
-    println!("{:?}", monitor_to_enable);
+    let mut plan = Plan::default();
 
+    // Disable first, so the CRTCs are free for the enabled outputs
     let mut disable_args : Vec<String> = Vec::new();
-
     for po in ports_to_disable
     {
         disable_args.push("--output".to_string());
-        disable_args.push(po);
+        disable_args.push(po.clone());
         disable_args.push("--off".to_string());
     }
+    if !disable_args.is_empty() {
+        plan.commands.push(disable_args);
+    }
+
+    let mut known_modes : Vec<String> = curr_layout.connected_outputs
+        .iter()
+        .flat_map(|x| x.modes.iter().map(|m| m.name.clone()))
+        .collect();
+    let mut scaled : Vec<MonitorConfig> = monitor_to_enable.iter().map(|x| x.0.clone()).collect();
+    for (config, &(_, output)) in scaled.iter_mut().zip(&monitor_to_enable) {
+        // No mode means the preferred one
+        if config.mode.is_none() {
+            config.mode = Some(output.preferred_mode().map(|x| x.mode()).ok_or_else(|| Error::InvalidProfile(
+                format!("{} has no mode, and {} lists none", config.display_name, output.connection_name)))?);
+        }
+        // The created mode is named after its refresh rate, so it is
+        // selected without --rate
+        let created = match (&config.modeline, &config.mode) {
+            (Some(timing), Some(mode)) => modeline::create(timing, mode, config.rate, output, &mut known_modes),
+            _ => None,
+        };
+        if let Some((mode, commands)) = created {
+            config.mode = Some(mode);
+            config.rate = None;
+            plan.commands.extend(commands);
+            continue;
+        }
 
-    disable_args.iter().for_each(|x| print!("{} ", x));
-    println!();
+        // Unless the power supply was decided before, the policy on AC
+        // applies, and any other than saved replaces the saved rate
+        let rate_policy = config.refresh.or(profile.refresh).map_or(Rate::Saved, |x| x.rate(true));
+        if rate_policy != Rate::Saved {
+            config.rate = None;
+            config.timing = None;
+            if let Some(ref mut mode) = config.mode {
+                mode.refresh = None;
+            }
+        }
 
-    let xrandr_output = String::from_utf8(
-        Command::new("xrandr")
-            .args(disable_args)
-            .output()
-            .unwrap()
-            .stdout,
-    ).unwrap();
+        if config.modeline.is_none() {
+            // A mode the monitor does not list is replaced by the nearest one
+            let requested = config.mode.clone().expect("mode filled in above");
+            let rate = config.rate.or(requested.refresh);
+            let nearest = output.nearest_mode(&requested, config.rate)
+                .filter(|m| m.name != requested.name() || rate.is_some_and(|rate| m.refresh > 0.0 && !m.has_refresh(rate)));
+            if let Some(nearest) = nearest {
+                let refresh = if nearest.refresh > 0.0 { Some(nearest.refresh) } else { None };
+                let substitute = Mode { refresh, ..nearest.mode() };
+                plan.notes.push(format!("{}: {} is not available, using {}",
+                    output.connection_name, Mode { refresh: rate, ..requested }, substitute));
+                config.mode = Some(substitute);
+                config.rate = None;
+                config.timing = None;
+            }
+        }
 
-    let mut enable_args : Vec<String> = Vec::new();
+        let name = config.mode.as_ref().map(Mode::name).unwrap_or_default();
+        let listed : Vec<&ModeInfo> = output.modes.iter().filter(|m| m.name == name && m.refresh > 0.0).collect();
+        if let Some(chosen) = rate_policy.choose(&listed) {
+            if let Rate::Hz(hz) = rate_policy {
+                if !chosen.has_refresh(hz) {
+                    plan.notes.push(format!("{}: {} Hz is not available for {}, using {:.2} Hz",
+                        output.connection_name, hz, name, chosen.refresh));
+                }
+            }
+            config.rate = Some(chosen.refresh);
+        }
+    }
+    if let Some(target) = profile.dpi.as_ref().and_then(|x| x.target) {
+        let outputs : Vec<&Output> = monitor_to_enable.iter().map(|x| x.1).collect();
+        dpi::scale_to_target(&mut scaled, &outputs, target)?;
+    }
+    // Kept monitors stay where they are, unless the layout has to move as a
+    // whole, and the listed ones can be placed next to them
+    let kept : Vec<(&Output, MonitorConfig)> = match policy {
+        Others::Keep => others.iter().filter_map(|x| Some((*x, capture_output(x)?))).collect(),
+        _ => Vec::new(),
+    };
+    let configs : Vec<&MonitorConfig> = scaled.iter().chain(kept.iter().map(|x| &x.1)).collect();
+    let mut positions = placement::solve(&configs)?;
+    let kept_positions = positions.split_off(scaled.len());
+
+    // The monitor marked primary, else the first turned on of primary_order
+    let primary = monitor_to_enable.iter().position(|x| x.0.primary).or_else(|| {
+        profile.primary_order.iter().find_map(|selector| {
+            monitor_to_enable.iter().position(|x| x.0.selected_by(selector) || x.1.selected_by(selector))
+        })
+    });
 
-    for po in monitor_to_enable
+    let mut enable_args : Vec<String> = Vec::new();
+    for (i, ((&(_, output), config), position)) in monitor_to_enable.iter().zip(&scaled).zip(positions).enumerate()
     {
         enable_args.push("--output".to_string());
-        enable_args.push(po.1.connection_name.clone());
-
+        enable_args.push(output.connection_name.clone());
+
+        // Filled in above, and a rate given on its own wins over the one of
+        // the mode
+        let mode = config.mode.as_ref().expect("mode of a planned output");
+        // The saved timing picks the mode by its id among those of the
+        // same name
+        let exact = config.timing.as_ref().and_then(|timing| {
+            output.modes.iter().find(|m| m.name == mode.name() && m.timing.as_ref().is_some_and(|x| x.same_timing(timing)))
+        });
         enable_args.push("--mode".to_string());
-        let config_idx = configs.iter().position(|x| x.display_name == po.0).unwrap();
-        let config = configs.remove(config_idx);
-        enable_args.push(config.mode);
+        match exact {
+            Some(m) => enable_args.push(m.id.clone()),
+            None => {
+                enable_args.push(mode.name());
+                if let Some(rate) = config.rate.or(mode.refresh) {
+                    enable_args.push("--rate".to_string());
+                    enable_args.push(format!("{:.2}", rate));
+                }
+            }
+        }
+
+        if let Some(ref scale_from) = config.scale_from {
+            enable_args.push("--scale-from".to_string());
+            enable_args.push(scale_from.clone());
+        }
+
+        if let Some(scale) = config.scale {
+            enable_args.push("--scale".to_string());
+            enable_args.push(format!("{}x{}", scale, scale));
+        }
+
+        // Transformations stay until they are reset
+        let current = &output.transformation;
+        if let Some(transform) = config.transform {
+            enable_args.push("--transform".to_string());
+            enable_args.push(transform.to_string());
+        } else if current.transform.is_some() && config.scale.is_none() && config.scale_from.is_none() {
+            enable_args.push("--transform".to_string());
+            enable_args.push("none".to_string());
+        }
+
+        if let Some(filter) = config.filter {
+            enable_args.push("--filter".to_string());
+            enable_args.push(filter.name().to_string());
+        }
+
+        if config.reflect.is_some() || current.reflect.is_some() {
+            enable_args.push("--reflect".to_string());
+            enable_args.push(config.reflect.map_or("normal", Reflect::name).to_string());
+        }
 
-        enable_args.push("--pos".to_string());
-        enable_args.push(config.position);
+        if let Some(panning) = config.panning {
+            enable_args.push("--panning".to_string());
+            enable_args.push(panning.at(position).to_string());
+        } else if current.panning.is_some() {
+            enable_args.push("--panning".to_string());
+            enable_args.push("0x0".to_string());
+        }
+
+        let source = config.same_as.as_ref()
+            .and_then(|x| monitor_to_enable.iter().find(|y| !std::ptr::eq(y.1, output) && y.0.selected_by(x)));
+        match source {
+            Some(source) => {
+                enable_args.push("--same-as".to_string());
+                enable_args.push(source.1.connection_name.clone());
+            }
+            None => {
+                enable_args.push("--pos".to_string());
+                enable_args.push(format!("{}x{}", position.0, position.1));
+            }
+        }
 
         enable_args.push("--rotate".to_string());
+        enable_args.push(config.orientation.xrandr_name().to_string());
 
-        let orientation_str = match config.orientation {
-            Orientation::Normal => "normal",
-            Orientation::Inverted => "inverted",
-            Orientation::Left => "left",
-            Orientation::Right => "right",
-        };
+        if primary == Some(i) {
+            enable_args.push("--primary".to_string());
+        }
 
-        enable_args.push(orientation_str.to_string());
+        for (property, value) in &config.properties {
+            enable_args.push("--set".to_string());
+            enable_args.push(property.clone());
+            enable_args.push(value.clone());
+        }
+
+        let size = placement::screen_size(config)?;
+        plan.outputs.push(PlannedOutput {
+            output: output.connection_name.clone(),
+            x: position.0,
+            y: position.1,
+            width: size.0,
+            height: size.1,
+            rotate: config.orientation.xrandr_name().to_string(),
+            primary: primary == Some(i),
+        });
     }
 
-    enable_args.iter().for_each(|x| print!("{} ", x));
-    println!();
+    for ((output, config), position) in kept.iter().zip(kept_positions) {
+        if config.position != Some(position.into()) {
+            enable_args.push("--output".to_string());
+            enable_args.push(output.connection_name.clone());
+            enable_args.push("--pos".to_string());
+            enable_args.push(format!("{}x{}", position.0, position.1));
+        }
+        let size = placement::screen_size(config)?;
+        plan.outputs.push(PlannedOutput {
+            output: output.connection_name.clone(),
+            x: position.0,
+            y: position.1,
+            width: size.0,
+            height: size.1,
+            rotate: config.orientation.xrandr_name().to_string(),
+            primary: primary.is_none() && config.primary,
+        });
+    }
 
-    let xrandr_output = String::from_utf8(
-        Command::new("xrandr")
-            .args(enable_args)
-            .output()
-            .unwrap()
-            .stdout,
-    ).unwrap();
+    match policy {
+        Others::Auto => {
+            for output in &others {
+                enable_args.push("--output".to_string());
+                enable_args.push(output.connection_name.clone());
+                enable_args.push("--auto".to_string());
+            }
+        }
+        Others::RightOfLast | Others::BelowLast => {
+            for output in &others {
+                let mode = match output.preferred_mode() {
+                    Some(x) => x,
+                    None => continue,
+                };
+                // Next to the right- or bottommost monitor, aligned with it
+                let position = match policy {
+                    Others::RightOfLast => plan.outputs.iter()
+                        .max_by_key(|x| x.x + x.width)
+                        .map_or((0, 0), |x| (x.x + x.width, x.y)),
+                    _ => plan.outputs.iter()
+                        .max_by_key(|x| x.y + x.height)
+                        .map_or((0, 0), |x| (x.x, x.y + x.height)),
+                };
+                enable_args.extend(vec![
+                    "--output".to_string(), output.connection_name.clone(),
+                    "--mode".to_string(), mode.name.clone(),
+                    "--pos".to_string(), format!("{}x{}", position.0, position.1),
+                    "--rotate".to_string(), "normal".to_string(),
+                ]);
+                plan.outputs.push(PlannedOutput {
+                    output: output.connection_name.clone(),
+                    x: position.0,
+                    y: position.1,
+                    width: mode.width as isize,
+                    height: mode.height as isize,
+                    rotate: "normal".to_string(),
+                    primary: false,
+                });
+            }
+        }
+        Others::Off | Others::Keep => (),
+    }
+
+    if !enable_args.is_empty() {
+        plan.commands.push(enable_args);
+    }
 
-    println!("{}", xrandr_output);
+    Ok(plan)
+}
+
+pub fn run_plan(plan: &Plan, debug: bool) -> DResult<()>
+{
+    print!("{}", plan);
+    if debug {
+        return Ok(());
+    }
+
+    for command in &plan.commands {
+        invoke_xrandr(command)?;
+    }
+
+    Ok(())
+}
+
+pub fn parse_profile(s: &str, format: Format) -> DResult<Profile> {
+    match format.parse(s) {
+        Ok(x) => Ok(x),
+        Err(e) => {
+            // Layout files written before profiles existed are a bare list of monitors
+            match format.parse(s) {
+                Ok(outputs) => Ok(Profile { outputs, ..Profile::default() }),
+                Err(_) => Err(e),
+            }
+        }
+    }
+}
+
+pub fn generate_profile(p: &Profile, format: Format) -> DResult<String> {
+    format.generate(p)
 }
 
-/*
 pub fn parse_json(s: &str) -> DResult<ConfigFile> {
     Ok(serde_json::from_str(s)?)
 }
@@ -487,13 +1464,78 @@ pub fn generate_json(p: &ConfigFile) -> DResult<String> {
     Ok(serde_json::to_string_pretty(p)?)
 }
 
+/// Replaces the file at `path` atomically.
+///
+/// The contents go to a temporary file in the same directory first, which is
+/// synced and then renamed over the target, so a crash leaves either the old
+/// or the new file behind, never a truncated one.
 pub fn save_file(path: &Path, contents: &str) -> DResult<()> {
-    let file = File::create(path)?;
-    let mut buf_writer = BufWriter::new(file);
-    buf_writer.write(contents.as_bytes())?;
-    buf_writer.get_ref().sync_all()?;
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let file_name = path.file_name().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not a file", path.display()))
+    })?;
+
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(file_name);
+    tmp_name.push(format!(".tmp{}", process::id()));
+    let tmp_path = dir.join(tmp_name);
+
+    let result = (|| -> DResult<()> {
+        let file = File::create(&tmp_path)?;
+        if let Ok(metadata) = fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
 
-    Ok(())
+        let mut buf_writer = BufWriter::new(file);
+        buf_writer.write_all(contents.as_bytes())?;
+        buf_writer.flush()?;
+        buf_writer.get_ref().sync_all()?;
+
+        fs::rename(&tmp_path, path)?;
+
+        // Persist the rename itself
+        File::open(dir)?.sync_all()?;
+        Ok(())
+    })();
+
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp_path);
+    }
+    result
+}
+
+/// An exclusive advisory lock, held until dropped.
+pub struct FileLock {
+    _file: File,
+}
+
+impl FileLock {
+    /// Blocks until the lock file at `path` is locked exclusively by this process.
+    pub fn acquire(path: &Path) -> DResult<FileLock> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+
+        loop {
+            // SAFETY: the descriptor belongs to `file`, which outlives the call
+            let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) };
+            if ret == 0 {
+                break;
+            }
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e.into());
+            }
+        }
+
+        // Closing the file releases the lock
+        Ok(FileLock { _file: file })
+    }
 }
 
 pub fn load_file(path: &Path) -> DResult<String> {
@@ -504,13 +1546,395 @@ pub fn load_file(path: &Path) -> DResult<String> {
     Ok(ret)
 }
 
+/// The per-user profile database, `config.yaml` unless a toml or json one exists.
 pub fn xdg_config_file() -> DResult<PathBuf> {
     let xdg_dirs = xdg::BaseDirectories::with_prefix("quickrandr")?;
-    Ok(xdg_dirs.place_config_file("config.json")?)
+    for format in &Format::ALL {
+        let path = xdg_dirs.get_config_file(format!("config.{}", format.extension()));
+        if path.is_file() {
+            return Ok(path);
+        }
+    }
+    Ok(xdg_dirs.place_config_file("config.yaml")?)
 }
 
+pub fn save_layout(path : &Path, debug: bool) -> DResult<()>
+{
+    let curr_layout = load_xrandr_layout()?;
+    let contents = generate_profile(&capture_profile(&curr_layout), Format::from_path(path))?;
+
+    if debug {
+        println!("DEBUG: Write to path {:?}:\n{}", path.display(), contents);
+    } else {
+        save_file(path, &contents)?;
+    }
+
+    Ok(())
+}
 
+pub fn load_layout(path : &Path, debug: bool) -> DResult<()>
+{
+    let profile = parse_profile(&load_file(path)?, Format::from_path(path))?;
+    let profile = Database::default().resolve_profile(&profile)?;
+    let curr_layout = load_xrandr_layout()?;
+
+    apply_profile(&path.display().to_string(), &profile, &Database::default(), &curr_layout, debug)
+}
+
+/// Applies a resolved profile, with the settings of the merged database
+/// `global` around its own.
+fn apply_profile(name: &str, profile: &Profile, global: &Database, curr_layout: &ConfigAndXrandr,
+                 debug: bool) -> DResult<()> {
+    let dpi_config = match (&global.dpi, &profile.dpi) {
+        (Some(a), Some(b)) => Some(a.merge(b)),
+        (a, b) => a.clone().or_else(|| b.clone()),
+    };
+    let mut planned = Profile { dpi: dpi_config.clone(), ..profile.clone() };
+
+    // Refresh rates that depend on the power supply are decided now
+    let ac = Context::current().ac;
+    planned.refresh = planned.refresh.map(|x| x.on_power(ac));
+    for config in &mut planned.outputs {
+        config.refresh = config.refresh.map(|x| x.on_power(ac));
+    }
+    let mut plan = plan_profile(&planned, curr_layout)?;
 
+    let dpi = dpi_config.as_ref().and_then(|x| x.dpi(&plan, &curr_layout.connected_outputs));
+    if let (Some(config), Some(dpi)) = (&dpi_config, dpi) {
+        if config.xrandr == Some(true) {
+            plan.commands.push(vec!["--dpi".to_string(), dpi.to_string()]);
+        }
+    }
+
+    let hooks: Vec<&Hooks> = global.hooks.iter().chain(profile.hooks.as_ref()).collect();
+    hooks::run_pre_hooks(name, &plan, &hooks, debug)?;
+    run_plan(&plan, debug)?;
+
+    if let (Some(config), Some(dpi)) = (&dpi_config, dpi) {
+        if config.xrdb == Some(true) {
+            dpi::merge_xrdb(&dpi::resources(dpi, config.cursor == Some(true)), debug)?;
+        }
+    }
+
+    for e in hooks::run_post_hooks(name, &plan, &hooks, debug) {
+        eprintln!("Warning: {}", e);
+    }
+    Ok(())
+}
+
+fn print_profile(name: &str, profile: &Profile) {
+    println!("{}:", name);
+    if let Some(ref parent) = profile.extends {
+        println!("    extends {}", parent);
+    }
+    if !profile.primary_order.is_empty() {
+        println!("    primary {}", profile.primary_order.join(", then "));
+    }
+    if let Some(refresh) = profile.refresh {
+        println!("    refresh {}", refresh);
+    }
+    if let Some(others) = profile.others {
+        println!("    others {}", others);
+    }
+    if let Some(ref when) = profile.when {
+        print!("    when");
+        if let Some(lid) = when.lid {
+            print!(" lid {}", if lid == Lid::Open { "open" } else { "closed" });
+        }
+        if let Some(ac) = when.ac {
+            print!(" {}", if ac { "on AC" } else { "on battery" });
+        }
+        if let Some(ref hostname) = when.hostname {
+            print!(" on {}", hostname);
+        }
+        if let Some(ref time) = when.time {
+            print!(" at {}", time);
+        }
+        println!();
+    }
+    for config in &profile.outputs {
+        print!("    ");
+        if let Some(ref connector) = config.connector {
+            print!("{}: ", connector);
+        }
+        match config.monitor() {
+            Some(monitor) => print!("{}", monitor),
+            None => print!("{}", config.display_name),
+        }
+        match config.mode {
+            Some(ref mode) => print!(" {}", mode),
+            None => print!(" preferred"),
+        }
+        if let Some(rate) = config.rate {
+            print!("@{:.2}", rate);
+        }
+        if let Some(refresh) = config.refresh {
+            print!(" refresh {}", refresh);
+        }
+        match config.modeline {
+            Some(Timing::Modeline(_)) => print!(" custom"),
+            Some(ref timing) => print!(" custom {}", timing),
+            None => (),
+        }
+        if let Some(scale) = config.scale {
+            print!(" scale {}", scale);
+        }
+        if let Some(ref scale_from) = config.scale_from {
+            print!(" scaled from {}", scale_from);
+        }
+        if config.transform.is_some() {
+            print!(" transformed");
+        }
+        if let Some(panning) = config.panning {
+            print!(" panning {}x{}", panning.area.width, panning.area.height);
+        }
+        match config.relation() {
+            Ok(Some((side, target))) => {
+                print!(" {} {}", side, target);
+                if let Some(align) = config.align {
+                    print!(" align {}", align.name());
+                }
+                if config.offset != 0 {
+                    print!(" offset {}", config.offset);
+                }
+            }
+            _ => print!(" at {}", config.position.unwrap_or_default()),
+        }
+        print!(" {}", config.orientation.xrandr_name());
+        if config.primary {
+            print!(" primary");
+        }
+        if !config.enabled {
+            print!(" disabled");
+        }
+        println!();
+    }
+    for x in &profile.overrides {
+        print!("    override {}:", x.output);
+        if let Some(ref mode) = x.mode {
+            print!(" mode {}", mode);
+        }
+        if let Some(rate) = x.rate {
+            print!(" rate {:.2}", rate);
+        }
+        if let Some(ref position) = x.position {
+            print!(" at {}", position);
+        }
+        if let Some(ref orientation) = x.orientation {
+            print!(" {}", orientation.xrandr_name());
+        }
+        if let Some(primary) = x.primary {
+            print!(" {}", if primary { "primary" } else { "not primary" });
+        }
+        if let Some(enabled) = x.enabled {
+            print!(" {}", if enabled { "enabled" } else { "disabled" });
+        }
+        println!();
+    }
+}
+
+fn write_database(database: &LayeredDatabase, debug: bool) -> DResult<()> {
+    if debug {
+        let contents = database.generate()?;
+        println!("DEBUG: Write to path {:?}:\n{}", database.user_path().display(), contents);
+        Ok(())
+    } else {
+        database.save()
+    }
+}
+
+/// Stores the current display configuration as profile `name`.
+pub fn cmd_save(sources: &ConfigSources, name: &str, force: bool, debug: bool) -> DResult<()> {
+    let curr_layout = load_xrandr_layout()?;
+    let _lock = sources.lock()?;
+    let mut database = sources.load()?;
+
+    database.insert(name, capture_profile(&curr_layout), force)?;
+    write_database(&database, debug)
+}
+
+/// Records that profile `name` was applied, so `--auto` prefers it in a tie.
+fn record_usage(name: &str, debug: bool) {
+    if debug {
+        return;
+    }
+    if let Err(e) = auto::usage_file().and_then(|x| auto::record_usage(&x, name)) {
+        eprintln!("Warning: cannot record the use of profile {}: {}", name, e);
+    }
+}
+
+/// When profiles were applied last, which only breaks ties, so an unreadable
+/// record is as good as none.
+fn last_used() -> auto::LastUsed {
+    match auto::usage_file().and_then(|x| auto::load_usage(&x)) {
+        Ok(usage) => usage,
+        Err(e) => {
+            eprintln!("Warning: cannot read when profiles were applied last: {}", e);
+            auto::LastUsed::new()
+        }
+    }
+}
+
+pub fn cmd_apply(sources: &ConfigSources, name: &str, debug: bool) -> DResult<()> {
+    let database = sources.load()?.merged();
+    let profile = database.resolve(name)?;
+    let curr_layout = load_xrandr_layout()?;
+
+    apply_profile(name, &profile, &database, &curr_layout, debug)?;
+    record_usage(name, debug);
+    Ok(())
+}
+
+/// Applies a layout generated from the connected monitors.
+pub fn cmd_apply_preset(sources: &ConfigSources, preset: &PresetConfig, debug: bool) -> DResult<()> {
+    let database = sources.load()?.merged();
+    let curr_layout = load_xrandr_layout()?;
+    let profile = preset.generate(&curr_layout.connected_outputs)?;
+
+    apply_profile(preset.preset.name(), &profile, &database, &curr_layout, debug)
+}
+
+/// Applies the profile fitting the connected monitors, or the fallback preset.
+pub fn cmd_auto(sources: &ConfigSources, debug: bool) -> DResult<()> {
+    let database = sources.load()?.merged();
+    let curr_layout = load_xrandr_layout()?;
+    let usage = last_used();
+    let context = Context::current();
+    let (choice, profile) = auto::choose(&database, &curr_layout.connected_outputs, &usage, &context)?;
+    println!("Using {}", choice);
+
+    match choice {
+        Choice::Profile(name, _) | Choice::Fallback(name) => {
+            apply_profile(&name, &profile, &database, &curr_layout, debug)?;
+            record_usage(&name, debug);
+        }
+        Choice::Preset(preset) => {
+            apply_profile(preset.preset.name(), &profile, &database, &curr_layout, debug)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn cmd_list(sources: &ConfigSources) -> DResult<()> {
+    let database = sources.load()?.merged();
+    for (name, profile) in &database.profiles {
+        print_profile(name, profile);
+    }
+    Ok(())
+}
+
+/// Prints a profile, or with `resolved` the result of merging it with its parents.
+pub fn cmd_show(sources: &ConfigSources, name: &str, resolved: bool) -> DResult<()> {
+    let database = sources.load()?.merged();
+    if resolved {
+        print_profile(name, &database.resolve(name)?);
+    } else {
+        print_profile(name, database.get(name)?);
+    }
+    Ok(())
+}
+
+pub fn cmd_rename(sources: &ConfigSources, from: &str, to: &str, force: bool, debug: bool) -> DResult<()> {
+    let _lock = sources.lock()?;
+    let mut database = sources.load()?;
+    database.rename(from, to, force)?;
+    write_database(&database, debug)
+}
+
+pub fn cmd_copy(sources: &ConfigSources, from: &str, to: &str, force: bool, debug: bool) -> DResult<()> {
+    let _lock = sources.lock()?;
+    let mut database = sources.load()?;
+    database.copy(from, to, force)?;
+    write_database(&database, debug)
+}
+
+pub fn cmd_delete(sources: &ConfigSources, name: &str, debug: bool) -> DResult<()> {
+    let _lock = sources.lock()?;
+    let mut database = sources.load()?;
+    database.remove(name)?;
+    write_database(&database, debug)
+}
+
+pub fn cmd_set(sources: &ConfigSources, name: &str, key: &str, value: &str, debug: bool) -> DResult<()> {
+    let _lock = sources.lock()?;
+    let mut database = sources.load()?;
+    database.get_mut(name)?.set(key, value)?;
+    write_database(&database, debug)
+}
+
+/// Writes profile `name` to stdout as a standalone layout file.
+pub fn cmd_export(sources: &ConfigSources, name: &str, format: Format) -> DResult<()> {
+    let database = sources.load()?.merged();
+    print!("{}", database.export(name, format)?);
+    Ok(())
+}
+
+/// Reads a layout file from stdin and stores it as profile `name`.
+pub fn cmd_import(sources: &ConfigSources, name: &str, format: Format, force: bool, debug: bool) -> DResult<()> {
+    let mut contents = String::new();
+    io::stdin().read_to_string(&mut contents)?;
+    let profile = parse_profile(&contents, format)?;
+
+    let _lock = sources.lock()?;
+    let mut database = sources.load()?;
+    database.insert(name, profile, force)?;
+    write_database(&database, debug)
+}
+
+/// Rewrites a layout file or profile database in the format of the output file name.
+pub fn cmd_convert(input: &Path, output: &Path, debug: bool) -> DResult<()> {
+    let contents = load_file(input)?;
+    let from = Format::from_path(input);
+    let to = Format::from_path(output);
+
+    // Anything with profiles in it is a database, everything else a layout file
+    let converted = match from.parse::<Database>(&contents) {
+        Ok(ref database) if !database.profiles.is_empty() => database.generate(to)?,
+        _ => match from.parse::<ConfigFile>(&contents) {
+            Ok(ref config) => legacy::convert(config)?.generate(to)?,
+            Err(_) => generate_profile(&parse_profile(&contents, from)?, to)?,
+        },
+    };
+
+    if debug {
+        println!("DEBUG: Write to path {:?}:\n{}", output.display(), converted);
+        Ok(())
+    } else {
+        save_file(output, &converted)
+    }
+}
+
+/// Prints all config files in merge order, and which of them each profile comes from.
+pub fn cmd_config_sources(sources: &ConfigSources) -> DResult<()> {
+    let database = sources.load()?;
+
+    println!("Config files, lowest priority first:");
+    for (path, _) in &database.layers {
+        print!("    {}", path.display());
+        if path.as_path() == database.user_path() {
+            print!(" (user)");
+        }
+        if !path.exists() {
+            print!(" (missing)");
+        }
+        println!();
+    }
+
+    println!("Profiles:");
+    for name in database.merged().profiles.keys() {
+        let mut origins = database.origins(name);
+        let origin = origins.pop().expect("profile origin");
+        print!("    {}: {}", name, origin.display());
+        for shadowed in origins.iter().rev() {
+            print!(", replaces {}", shadowed.display());
+        }
+        println!();
+    }
+
+    Ok(())
+}
+
+/*
 pub fn save_config(path: &Path, config_file: &ConfigFile) -> DResult<()> {
     save_file(path, &generate_json(config_file)?)?;
 
@@ -1,97 +1,253 @@
 extern crate quickrandr;
 extern crate clap;
 
-use clap::{Arg, App, ArgGroup};
+use std::path::Path;
+use std::process;
+
+use quickrandr::database::ConfigSources;
+use quickrandr::format::Format;
+use quickrandr::placement::Align;
+use quickrandr::preset::{Preset, PresetConfig};
+
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
+
+fn profile_arg(name: &'static str, help: &'static str) -> Arg<'static, 'static> {
+    Arg::with_name(name)
+        .value_name("PROFILE")
+        .help(help)
+        .required(true)
+}
+
+fn force_arg() -> Arg<'static, 'static> {
+    Arg::with_name("force")
+        .short("f")
+        .long("force")
+        .help("Overwrites an existing profile of the same name.")
+}
+
+fn format_arg() -> Arg<'static, 'static> {
+    Arg::with_name("format")
+        .long("format")
+        .value_name("FORMAT")
+        .help("Format of the layout file.")
+        .possible_values(&["yaml", "toml", "json"])
+        .default_value("yaml")
+}
+
+fn format_of(matches: &ArgMatches) -> Format {
+    matches.value_of("format").and_then(Format::from_name).unwrap_or(Format::Yaml)
+}
+
+fn run(matches: &ArgMatches, sources: &ConfigSources, debug: bool) -> quickrandr::DResult<()> {
+    if let Some(config_file) = matches.value_of_os("save")
+    {
+        return quickrandr::save_layout(Path::new(config_file), debug);
+    }
+
+    if let Some(config_file) = matches.value_of_os("load")
+    {
+        return quickrandr::load_layout(Path::new(config_file), debug);
+    }
+
+    if matches.is_present("auto") {
+        return quickrandr::cmd_auto(sources, debug);
+    }
+
+    match matches.subcommand() {
+        ("save", Some(m)) => quickrandr::cmd_save(sources, m.value_of("name").unwrap(), m.is_present("force"), debug),
+        ("apply", Some(m)) => match m.value_of("preset").and_then(Preset::from_name) {
+            Some(preset) => {
+                let preset = PresetConfig {
+                    preset,
+                    align: m.value_of("align").and_then(Align::from_name),
+                    order: m.values_of("order").map(|x| x.map(String::from).collect()).unwrap_or_default(),
+                };
+                quickrandr::cmd_apply_preset(sources, &preset, debug)
+            }
+            None => quickrandr::cmd_apply(sources, m.value_of("name").unwrap(), debug),
+        },
+        ("list", Some(_)) => quickrandr::cmd_list(sources),
+        ("show", Some(m)) => quickrandr::cmd_show(sources, m.value_of("name").unwrap(), m.is_present("resolved")),
+        ("rename", Some(m)) => quickrandr::cmd_rename(sources, m.value_of("from").unwrap(), m.value_of("to").unwrap(), m.is_present("force"), debug),
+        ("copy", Some(m)) => quickrandr::cmd_copy(sources, m.value_of("from").unwrap(), m.value_of("to").unwrap(), m.is_present("force"), debug),
+        ("set", Some(m)) => quickrandr::cmd_set(sources, m.value_of("name").unwrap(), m.value_of("key").unwrap(), m.value_of("value").unwrap(), debug),
+        ("delete", Some(m)) => quickrandr::cmd_delete(sources, m.value_of("name").unwrap(), debug),
+        ("export", Some(m)) => quickrandr::cmd_export(sources, m.value_of("name").unwrap(), format_of(m)),
+        ("import", Some(m)) => quickrandr::cmd_import(sources, m.value_of("name").unwrap(), format_of(m), m.is_present("force"), debug),
+        ("convert", Some(m)) => quickrandr::cmd_convert(Path::new(m.value_of_os("input").unwrap()), Path::new(m.value_of_os("output").unwrap()), debug),
+        ("config", Some(m)) => match m.subcommand() {
+            ("sources", Some(_)) => quickrandr::cmd_config_sources(sources),
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
 
 fn main() {
     let matches = App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
         .author(env!("CARGO_PKG_AUTHORS"))
         .about(env!("CARGO_PKG_DESCRIPTION"))
-        .group(ArgGroup::with_name("main-options")
-            .args(&["save", "load", "auto"])
-            .required(true)
-        )
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("auto")
             .short("a")
             .long("auto")
-            .help("Automatically configures the displays according to the config file.")
+            .help("Applies the profile matching the connected monitors, or the fallback preset of the config file.")
+        )
+        .arg(Arg::with_name("config")
+            .short("c")
+            .long("config")
+            .value_name("FILE")
+            .help("Uses only the given profile database. If not given, the shared config directories and the users \
+                   config file directory are used.")
+            .takes_value(true)
+            .global(true)
+        )
+        .arg(Arg::with_name("debug")
+            .long("debug")
+            .help("Does verbose printing, and only simulates calls to xrandr and writes to files.")
+            .global(true)
         )
-        /*
-                    .arg(Arg::with_name("default-profile")
-                        .short("d")
-                        .long("default-profile")
-                        .value_name("PROFILE")
-                        .help("Selects a profile to apply in case --auto does not recognize the current system config.")
-                        .takes_value(true)
-                        .requires("auto")
-                    )
-                    .arg(Arg::with_name("profile")
-                        .short("p")
-                        .long("profile")
-                        .value_name("PROFILE")
-                        .help("Applies the given profile.")
-                        .takes_value(true)
-                    )
-                    */
-
         .arg(Arg::with_name("save")
             .short("s")
             .long("save")
-            .help("Generates a config file from the current config")
+            .help("Generates a config file from the current config, in yaml, toml or json format depending on the file \
+                   extension")
             .value_name("CONFIG_FILE")
             .takes_value(true)
+            .conflicts_with_all(&["load", "auto"])
         ).arg(Arg::with_name("load")
         .short("l")
         .long("load")
         .help("Loads a display configuration from the provided file")
         .value_name("CONFIG_FILE")
         .takes_value(true)
-    ).get_matches();
-
-    if matches.is_present("save")
-    {
-        let config_file =  matches.value_of("save").unwrap();
-        quickrandr::save_layout(config_file);
-        println!("{:?}", config_file);
-    }
-
-    if matches.is_present("load")
-    {
-        let config_file =  matches.value_of("load").unwrap();
-        quickrandr::load_layout(config_file);
-        println!("{:?}", config_file);
-    }
+        .conflicts_with("auto")
+    )
+        .subcommand(SubCommand::with_name("save")
+            .about("Stores the current display configuration as a profile.")
+            .arg(profile_arg("name", "Name of the profile to create."))
+            .arg(force_arg())
+        )
+        .subcommand(SubCommand::with_name("apply")
+            .about("Applies a stored profile, or a layout generated from the connected monitors.")
+            .arg(profile_arg("name", "Name of the profile to apply.")
+                .required_unless("preset")
+            )
+            .arg(Arg::with_name("preset")
+                .long("preset")
+                .value_name("PRESET")
+                .help("Arranges the connected monitors in their preferred modes instead of applying a profile.")
+                .possible_values(&["extend-row", "stack", "mirror", "internal-only", "externals-only"])
+                .conflicts_with("name")
+            )
+            .arg(Arg::with_name("align")
+                .long("align")
+                .value_name("EDGE")
+                .help("Edge the monitors of a row or stack line up on.")
+                .possible_values(&["top", "bottom", "left", "right", "center"])
+                .requires("preset")
+            )
+            .arg(Arg::with_name("order")
+                .long("order")
+                .value_name("OUTPUTS")
+                .help("Comma separated monitors to put first, by connector, display name or serial.")
+                .use_delimiter(true)
+                .requires("preset")
+            )
+        )
+        .subcommand(SubCommand::with_name("list")
+            .about("Lists all profiles and the monitors they use.")
+        )
+        .subcommand(SubCommand::with_name("show")
+            .about("Shows a single profile.")
+            .arg(profile_arg("name", "Name of the profile to show."))
+            .arg(Arg::with_name("resolved")
+                .short("r")
+                .long("resolved")
+                .help("Shows the profile merged with the profiles it extends.")
+            )
+        )
+        .subcommand(SubCommand::with_name("rename")
+            .about("Renames a profile.")
+            .arg(profile_arg("from", "Current name of the profile."))
+            .arg(profile_arg("to", "New name of the profile."))
+            .arg(force_arg())
+        )
+        .subcommand(SubCommand::with_name("copy")
+            .about("Duplicates a profile under a new name.")
+            .arg(profile_arg("from", "Name of the profile to copy."))
+            .arg(profile_arg("to", "Name of the new profile."))
+            .arg(force_arg())
+        )
+        .subcommand(SubCommand::with_name("set")
+            .about("Changes a single setting of a profile, keeping the rest of the file as it is.")
+            .arg(profile_arg("name", "Name of the profile to change."))
+            .arg(Arg::with_name("key")
+                .value_name("KEY")
+                .help("Either extends, priority, dpi, primary_order (comma separated), others, refresh, or <OUTPUT>.<FIELD> with a connector, display name or serial as OUTPUT and \
+                       mode, rate, refresh, modeline, scale_from, scale, filter, reflect, transform, panning, position, right_of, left_of, above, below, same_as, align, offset, orientation, primary or \
+                       enabled as FIELD.")
+                .required(true)
+            )
+            .arg(Arg::with_name("value")
+                .value_name("VALUE")
+                .help("The new value.")
+                .required(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("delete")
+            .about("Deletes a profile.")
+            .arg(profile_arg("name", "Name of the profile to delete."))
+        )
+        .subcommand(SubCommand::with_name("export")
+            .about("Prints a profile as a standalone layout file.")
+            .arg(profile_arg("name", "Name of the profile to export."))
+            .arg(format_arg())
+        )
+        .subcommand(SubCommand::with_name("import")
+            .about("Reads a layout file from stdin and stores it as a profile.")
+            .arg(profile_arg("name", "Name of the profile to create."))
+            .arg(force_arg())
+            .arg(format_arg())
+        )
+        .subcommand(SubCommand::with_name("convert")
+            .about("Rewrites a layout file or profile database in the format given by the output file extension.")
+            .arg(Arg::with_name("input")
+                .value_name("INPUT")
+                .help("File to read, in yaml, toml or json format depending on its extension.")
+                .required(true)
+            )
+            .arg(Arg::with_name("output")
+                .value_name("OUTPUT")
+                .help("File to write, in yaml, toml or json format depending on its extension.")
+                .required(true)
+            )
+        )
+        .subcommand(SubCommand::with_name("config")
+            .about("Inspects the configuration.")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("sources")
+                .about("Shows all config files in merge order, and where each profile comes from.")
+            )
+        )
+        .get_matches();
 
-    /*
     let debug = matches.is_present("debug");
-    let config_path = if let Some(p) = matches.value_of_os("config") {
-        p.into()
-    } else {
-        quickrandr::xdg_config_file().unwrap()
+    let sources = match matches.value_of_os("config") {
+        Some(p) => ConfigSources::single(p.into()),
+        None => match ConfigSources::discover() {
+            Ok(x) => x,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(1);
+            }
+        },
     };
 
-    if matches.is_present("auto") {
-        let default_profile = matches.value_of("default-profile");
-
-        quickrandr::cmd_auto(&config_path, default_profile, debug);
-        return;
-    }
-    if matches.is_present("create-empty") {
-        quickrandr::cmd_create_empty(&config_path, debug);
-        return;
-    }
-    if matches.is_present("info") {
-        quickrandr::cmd_info(&config_path, debug);
-        return;
-    }
-    if matches.is_present("save") {
-        quickrandr::cmd_save(&config_path, debug);
-        return;
-    }
-    if let Some(profile) = matches.value_of("profile") {
-        quickrandr::cmd_profile(&config_path, profile, debug);
-        return;
+    if let Err(e) = run(&matches, &sources, debug) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
     }
-     */
 }
@@ -0,0 +1,149 @@
+//! Display modes and positions as written in profiles.
+//!
+//! A mode is written like xrandr names it, `1920x1080` or `1920x1080i`,
+//! optionally followed by a refresh rate as in `1920x1080@59.95`. A position
+//! is `1200x352`, or `+1200+352` as in the geometry xrandr prints.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Mode {
+    pub width: usize,
+    pub height: usize,
+    /// Refresh rate in Hz, the best one for the size if not given.
+    pub refresh: Option<f64>,
+    /// Rest of the xrandr mode name after the size, like `i` for interlaced
+    /// modes.
+    pub flags: String,
+}
+
+impl Mode {
+    pub fn new(width: usize, height: usize) -> Mode {
+        Mode { width, height, refresh: None, flags: String::new() }
+    }
+
+    /// The name xrandr knows the mode by, without the refresh rate.
+    pub fn name(&self) -> String {
+        format!("{}x{}{}", self.width, self.height, self.flags)
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())?;
+        if let Some(refresh) = self.refresh {
+            write!(f, "@{}", refresh)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Mode, String> {
+        let error = || format!("expected a mode like 1920x1080 or 1920x1080@59.95, got {}", s);
+        let (name, refresh) = match s.split_once('@') {
+            Some((name, refresh)) => (name, Some(refresh.parse().map_err(|_| error())?)),
+            None => (s, None),
+        };
+        let (width, rest) = name.split_once('x').ok_or_else(error)?;
+        let digits = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+        Ok(Mode {
+            width: width.parse().map_err(|_| error())?,
+            height: rest[..digits].parse().map_err(|_| error())?,
+            refresh,
+            flags: rest[digits..].to_string(),
+        })
+    }
+}
+
+impl TryFrom<String> for Mode {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Mode, String> {
+        s.parse()
+    }
+}
+
+impl From<Mode> for String {
+    fn from(mode: Mode) -> String {
+        mode.to_string()
+    }
+}
+
+/// Top left corner of a monitor on the screen.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Position {
+    pub x: isize,
+    pub y: isize,
+}
+
+impl Position {
+    pub fn new(x: isize, y: isize) -> Position {
+        Position { x, y }
+    }
+}
+
+impl From<(isize, isize)> for Position {
+    fn from((x, y): (isize, isize)) -> Position {
+        Position { x, y }
+    }
+}
+
+impl From<Position> for (isize, isize) {
+    fn from(position: Position) -> (isize, isize) {
+        (position.x, position.y)
+    }
+}
+
+impl fmt::Display for Position {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}", self.x, self.y)
+    }
+}
+
+impl FromStr for Position {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Position, String> {
+        let error = || format!("expected a position like 1200x352 or +1200+352, got {}", s);
+        let s = s.trim();
+        // Tried first, since 1200x352 may be negative as well
+        let (x, y) = if let Some(xy) = s.split_once('x') {
+            xy
+        } else if s.starts_with(['+', '-']) {
+            // The sign of the second number separates them
+            let split = s[1..].find(['+', '-']).ok_or_else(error)? + 1;
+            (s[..split].trim_start_matches('+'), s[split..].trim_start_matches('+'))
+        } else {
+            return Err(error());
+        };
+        Ok(Position {
+            x: x.trim().parse().map_err(|_| error())?,
+            y: y.trim().parse().map_err(|_| error())?,
+        })
+    }
+}
+
+impl TryFrom<String> for Position {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Position, String> {
+        s.parse()
+    }
+}
+
+impl From<Position> for String {
+    fn from(position: Position) -> String {
+        position.to_string()
+    }
+}
@@ -0,0 +1,294 @@
+//! Modes a monitor does not list, created when a profile is applied.
+//!
+//! ~~~yaml
+//! mode: 2560x1440@75
+//! modeline: cvt-rb
+//! ~~~
+//!
+//! `modeline` is either `cvt` or `cvt-rb`, which compute the timing of the
+//! mode like `cvt` and `cvt -r` do, or an explicit modeline as in
+//! `241.50 2560 2608 2640 2720 1440 1443 1448 1481 +hsync -vsync`. If the
+//! output lists no mode of that size and refresh rate, it is created with
+//! `xrandr --newmode`, added to the output with `--addmode` and named like
+//! `2560x1440_75.00`. Without a refresh rate in the mode or `rate`, a listed
+//! mode of the size is used, or one at 60 Hz is created.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use mode::Mode;
+use Output;
+
+/// The timing of a mode, as given to `xrandr --newmode`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Modeline {
+    /// Pixel clock in MHz.
+    pub clock: f64,
+    /// Width, sync start, sync end and total width in pixels.
+    pub horizontal: [usize; 4],
+    /// Height, sync start, sync end and total height in lines.
+    pub vertical: [usize; 4],
+    /// Like `+hsync`, `-vsync` or `interlace`.
+    pub flags: Vec<String>,
+}
+
+impl Modeline {
+    pub fn width(&self) -> usize {
+        self.horizontal[0]
+    }
+
+    pub fn height(&self) -> usize {
+        self.vertical[0]
+    }
+
+    /// Vertical refresh rate in Hz.
+    pub fn refresh(&self) -> f64 {
+        let mut refresh = self.clock * 1e6 / (self.horizontal[3] * self.vertical[3]) as f64;
+        if self.has_flag("interlace") {
+            refresh *= 2.0;
+        }
+        if self.has_flag("doublescan") {
+            refresh /= 2.0;
+        }
+        refresh
+    }
+
+    fn has_flag(&self, flag: &str) -> bool {
+        self.flags.iter().any(|x| x.eq_ignore_ascii_case(flag))
+    }
+
+    /// Whether both describe the same mode, as xrandr rounds the clock and
+    /// writes the flags in any case.
+    pub fn same_timing(&self, other: &Modeline) -> bool {
+        self.horizontal == other.horizontal
+            && self.vertical == other.vertical
+            && (self.clock - other.clock).abs() < 0.005
+            && self.flags.len() == other.flags.len()
+            && self.flags.iter().all(|x| other.has_flag(x))
+    }
+
+    /// The arguments of `xrandr --newmode` after the name.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![format!("{:.2}", self.clock)];
+        args.extend(self.horizontal.iter().chain(&self.vertical).map(|x| x.to_string()));
+        args.extend(self.flags.iter().cloned());
+        args
+    }
+}
+
+impl fmt::Display for Modeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.args().join(" "))
+    }
+}
+
+impl FromStr for Modeline {
+    type Err = String;
+
+    /// Also accepts a line copied from `cvt`, like
+    /// `Modeline "2560x1440R" 241.50 2560 ...`, whose name is left out.
+    fn from_str(s: &str) -> Result<Modeline, String> {
+        let error = || format!("expected a modeline like 241.50 2560 2608 2640 2720 1440 1443 1448 1481 +hsync -vsync, got {}", s);
+        let mut words: Vec<&str> = s.split_whitespace().collect();
+        if words.first().is_some_and(|x| x.eq_ignore_ascii_case("modeline")) {
+            words.remove(0);
+        }
+        if words.first().is_some_and(|x| x.starts_with('"')) {
+            words.remove(0);
+        }
+        if words.len() < 9 {
+            return Err(error());
+        }
+        let clock = words[0].parse().map_err(|_| error())?;
+        let mut timings = [0; 8];
+        for (x, word) in timings.iter_mut().zip(&words[1..9]) {
+            *x = word.parse().map_err(|_| error())?;
+        }
+        Ok(Modeline {
+            clock,
+            horizontal: [timings[0], timings[1], timings[2], timings[3]],
+            vertical: [timings[4], timings[5], timings[6], timings[7]],
+            flags: words[9..].iter().map(|x| x.to_string()).collect(),
+        })
+    }
+}
+
+impl TryFrom<String> for Modeline {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Modeline, String> {
+        s.parse()
+    }
+}
+
+impl From<Modeline> for String {
+    fn from(modeline: Modeline) -> String {
+        modeline.to_string()
+    }
+}
+
+/// How the timing of a custom mode is found.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Timing {
+    /// Coordinated Video Timings, like `cvt`.
+    Cvt,
+    /// CVT with reduced blanking for digital monitors, like `cvt -r`.
+    CvtReduced,
+    Modeline(Modeline),
+}
+
+impl Timing {
+    /// The modeline of a mode of the given size and refresh rate.
+    pub fn modeline(&self, width: usize, height: usize, refresh: f64) -> Modeline {
+        match *self {
+            Timing::Cvt => cvt(width, height, refresh, false),
+            Timing::CvtReduced => cvt(width, height, refresh, true),
+            Timing::Modeline(ref modeline) => modeline.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Timing::Cvt => write!(f, "cvt"),
+            Timing::CvtReduced => write!(f, "cvt-rb"),
+            Timing::Modeline(ref modeline) => write!(f, "{}", modeline),
+        }
+    }
+}
+
+impl FromStr for Timing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Timing, String> {
+        match s.trim() {
+            "cvt" => Ok(Timing::Cvt),
+            "cvt-rb" => Ok(Timing::CvtReduced),
+            _ => s.parse().map(Timing::Modeline).map_err(|e: String| e.replacen("expected", "expected cvt, cvt-rb or", 1)),
+        }
+    }
+}
+
+impl TryFrom<String> for Timing {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Timing, String> {
+        s.parse()
+    }
+}
+
+impl From<Timing> for String {
+    fn from(timing: Timing) -> String {
+        timing.to_string()
+    }
+}
+
+/// The mode to select for `mode` on `output`, created from `timing` unless
+/// the output lists one of that size and refresh rate, with the xrandr
+/// arguments that create it.
+///
+/// `known` holds the names of the modes that exist already, those created
+/// here are added.
+pub fn create(timing: &Timing, mode: &Mode, rate: Option<f64>, output: &Output, known: &mut Vec<String>)
+    -> Option<(Mode, Vec<Vec<String>>)>
+{
+    // Without a rate, any listed mode of the size will do
+    let requested = match *timing {
+        Timing::Modeline(ref modeline) => Some(modeline.refresh()),
+        _ => mode.refresh.or(rate),
+    };
+    let refresh = requested.unwrap_or(60.0);
+    let modeline = timing.modeline(mode.width, mode.height, refresh);
+    let (width, height) = (modeline.width(), modeline.height());
+    let listed = output.modes
+        .iter()
+        .any(|x| x.width == width && x.height == height && requested.is_none_or(|r| (x.refresh - r).abs() < 0.05));
+    if listed {
+        return None;
+    }
+
+    let created = Mode { width, height, refresh: None, flags: format!("_{:.2}", refresh) };
+    let name = created.name();
+    let mut commands = Vec::new();
+    if !output.modes.iter().any(|x| x.name == name) {
+        if !known.contains(&name) {
+            let mut newmode = vec!["--newmode".to_string(), name.clone()];
+            newmode.extend(modeline.args());
+            commands.push(newmode);
+            known.push(name.clone());
+        }
+        commands.push(vec!["--addmode".to_string(), output.connection_name.clone(), name]);
+    }
+    Some((created, commands))
+}
+
+const H_GRANULARITY: usize = 8;
+const MIN_V_PORCH: usize = 3;
+const MIN_V_BACK_PORCH: usize = 6;
+/// Pixel clock step in kHz.
+const CLOCK_STEP: usize = 250;
+/// Minimum time of vertical sync and back porch in µs.
+const MIN_VSYNC_BP: f64 = 550.0;
+const HSYNC_PERCENTAGE: usize = 8;
+/// Blanking formula gradient and offset, after scaling.
+const M_PRIME: f64 = 300.0;
+const C_PRIME: f64 = 30.0;
+/// Minimum vertical blanking time of reduced blanking in µs.
+const RB_MIN_VBLANK: f64 = 460.0;
+const RB_H_SYNC: usize = 32;
+const RB_H_BLANK: usize = 160;
+
+/// Computes a progressive mode following the VESA CVT 1.1 formula, the same
+/// modeline `cvt` or, with `reduced`, `cvt -r` prints.
+pub fn cvt(width: usize, height: usize, refresh: f64, reduced: bool) -> Modeline {
+    let width = width - width % H_GRANULARITY;
+
+    // The vertical sync width tells the aspect ratio
+    let aspect = |w: usize, h: usize| height.is_multiple_of(h) && height * w / h == width;
+    let vsync = if aspect(4, 3) {
+        4
+    } else if aspect(16, 9) {
+        5
+    } else if aspect(16, 10) {
+        6
+    } else if aspect(5, 4) || aspect(15, 9) {
+        7
+    } else {
+        10
+    };
+
+    let frame = 1e6 / refresh;
+    let (horizontal, vtotal, clock, flags) = if reduced {
+        let period = (frame - RB_MIN_VBLANK) / height as f64;
+        let blank_lines = ((RB_MIN_VBLANK / period) as usize + 1).max(MIN_V_PORCH + vsync + MIN_V_BACK_PORCH);
+        let vtotal = height + blank_lines;
+        let htotal = width + RB_H_BLANK;
+        let clock = (refresh * (vtotal * htotal) as f64 / 1000.0) as usize;
+        let sync_end = width + RB_H_BLANK / 2;
+        ([width, sync_end - RB_H_SYNC, sync_end, htotal], vtotal, clock, ["+hsync", "-vsync"])
+    } else {
+        let period = (frame - MIN_VSYNC_BP) / (height + MIN_V_PORCH) as f64;
+        let sync_and_back_porch = ((MIN_VSYNC_BP / period) as usize + 1).max(vsync + MIN_V_PORCH);
+        let vtotal = height + sync_and_back_porch + MIN_V_PORCH;
+        let blank_percentage = (C_PRIME - M_PRIME * period / 1000.0).max(20.0);
+        let blank = (width as f64 * blank_percentage / (100.0 - blank_percentage)) as usize;
+        let blank = blank - blank % (2 * H_GRANULARITY);
+        let htotal = width + blank;
+        let clock = (htotal as f64 * 1000.0 / period) as usize;
+        let sync_end = width + blank / 2;
+        let sync_start = sync_end - htotal * HSYNC_PERCENTAGE / 100;
+        let sync_start = sync_start + H_GRANULARITY - sync_start % H_GRANULARITY;
+        ([width, sync_start, sync_end, htotal], vtotal, clock, ["-hsync", "+vsync"])
+    };
+
+    Modeline {
+        clock: (clock - clock % CLOCK_STEP) as f64 / 1000.0,
+        horizontal,
+        vertical: [height, height + MIN_V_PORCH, height + MIN_V_PORCH + vsync, vtotal],
+        flags: flags.iter().map(|x| x.to_string()).collect(),
+    }
+}
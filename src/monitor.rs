@@ -0,0 +1,83 @@
+//! Decoding of the monitor identity stored in an EDID blob.
+
+use std::fmt;
+
+/// The parts of an EDID that identify a physical monitor.
+#[derive(Hash, Ord, PartialOrd, Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct MonitorInfo {
+    /// Three letter PNP vendor id, e.g. `DEL`.
+    pub vendor: String,
+    pub product_code: u16,
+    /// Product name descriptor, or the best replacement for it.
+    pub model: String,
+    /// Serial number descriptor, or the numeric header serial if non-zero.
+    pub serial: Option<String>,
+    /// Physical size in centimeters, as reported in the EDID base block.
+    pub width_cm: u8,
+    pub height_cm: u8,
+}
+
+impl MonitorInfo {
+    /// Decodes a hex encoded EDID as printed by `xrandr --prop`.
+    pub fn from_hex(edid: &str) -> Option<MonitorInfo> {
+        let data = hex::decode(edid).ok()?;
+        MonitorInfo::from_bytes(&data)
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Option<MonitorInfo> {
+        // The parser panics on input shorter than the 128 byte base block
+        if data.len() < 128 {
+            return None;
+        }
+        let parsed = edid::parse(data).to_result().ok()?;
+
+        let mut product_name = None;
+        let mut serial = None;
+        let mut text = None;
+        for descriptor in &parsed.descriptors {
+            match *descriptor {
+                edid::Descriptor::ProductName(ref v) => product_name = Some(v.clone()),
+                edid::Descriptor::SerialNumber(ref v) => serial = Some(v.clone()),
+                edid::Descriptor::UnspecifiedText(ref v) => text = Some(v.clone()),
+                _ => (),
+            }
+        }
+
+        let vendor: String = parsed.header.vendor.iter().collect();
+        let product_code = parsed.header.product;
+
+        // Laptop panels usually lack a product name, but carry the panel
+        // model in an unspecified text descriptor instead.
+        let model = product_name
+            .or(text)
+            .filter(|x| !x.is_empty())
+            .unwrap_or_else(|| format!("{} {:04x}", vendor, product_code));
+
+        let serial = serial.filter(|x| !x.is_empty()).or_else(|| {
+            if parsed.header.serial != 0 {
+                Some(parsed.header.serial.to_string())
+            } else {
+                None
+            }
+        });
+
+        Some(MonitorInfo {
+            vendor,
+            product_code,
+            model,
+            serial,
+            width_cm: parsed.display.width,
+            height_cm: parsed.display.height,
+        })
+    }
+}
+
+impl fmt::Display for MonitorInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}", self.model, self.vendor)?;
+        if let Some(ref serial) = self.serial {
+            write!(f, ", serial {}", serial)?;
+        }
+        write!(f, ")")
+    }
+}
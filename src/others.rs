@@ -0,0 +1,54 @@
+//! What happens to connected monitors a profile does not list.
+//!
+//! ~~~yaml
+//! profiles:
+//!   dell-above:
+//!     others: keep
+//!     outputs:
+//!       - display_name: DELL U2415
+//!         above: internal
+//!         ...
+//! ~~~
+//!
+//! By default they are turned off. With `keep` they stay as they are, and
+//! the listed monitors can be placed relative to them. `auto` turns them on
+//! with `xrandr --auto`, `right-of-last` and `below-last` turn them on in
+//! their preferred mode next to the right- or bottommost monitor.
+
+use std::fmt;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Others {
+    #[default]
+    Off,
+    Keep,
+    Auto,
+    RightOfLast,
+    BelowLast,
+}
+
+impl Others {
+    pub const ALL: [Others; 5] = [Others::Off, Others::Keep, Others::Auto, Others::RightOfLast, Others::BelowLast];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Others::Off => "off",
+            Others::Keep => "keep",
+            Others::Auto => "auto",
+            Others::RightOfLast => "right-of-last",
+            Others::BelowLast => "below-last",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Others> {
+        Others::ALL.iter().cloned().find(|x| x.name() == name)
+    }
+
+}
+
+impl fmt::Display for Others {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
@@ -0,0 +1,332 @@
+//! Relative placement of monitors.
+//!
+//! Instead of an absolute `position`, a monitor can be placed `right_of`,
+//! `left_of`, `above` or `below` another one, optionally aligned along the
+//! shared edge and shifted by `offset` pixels, or mirror another monitor with
+//! `same_as`. The relations are solved to absolute coordinates when a profile
+//! is applied, using the modes of the monitors that are actually turned on,
+//! so the layout has no gaps or overlaps when a resolution differs from the
+//! one it was written for.
+//!
+//! Monitors with an absolute position can be moved along with the size
+//! changes of their neighbours by `reflow`.
+
+use std::fmt;
+
+use {DResult, Error, Geometry, MonitorConfig};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Align {
+    Top,
+    Bottom,
+    Left,
+    Right,
+    Center,
+}
+
+impl Align {
+    pub fn from_name(name: &str) -> Option<Align> {
+        match name {
+            "top" => Some(Align::Top),
+            "bottom" => Some(Align::Bottom),
+            "left" => Some(Align::Left),
+            "right" => Some(Align::Right),
+            "center" => Some(Align::Center),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Align::Top => "top",
+            Align::Bottom => "bottom",
+            Align::Left => "left",
+            Align::Right => "right",
+            Align::Center => "center",
+        }
+    }
+}
+
+/// The side of the reference monitor a monitor is placed on.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Side {
+    RightOf,
+    LeftOf,
+    Above,
+    Below,
+    /// Mirrors the other monitor.
+    SameAs,
+}
+
+impl Side {
+    pub const ALL: [Side; 5] = [Side::RightOf, Side::LeftOf, Side::Above, Side::Below, Side::SameAs];
+
+    /// The config file key of the relation.
+    pub fn name(self) -> &'static str {
+        match self {
+            Side::RightOf => "right_of",
+            Side::LeftOf => "left_of",
+            Side::Above => "above",
+            Side::Below => "below",
+            Side::SameAs => "same_as",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Side> {
+        Side::ALL.iter().cloned().find(|x| x.name() == name)
+    }
+
+    fn is_horizontal(self) -> bool {
+        self == Side::RightOf || self == Side::LeftOf
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Parses a `"WxH"` size.
+pub fn parse_size(size: &str) -> Option<(isize, isize)> {
+    let mut iter = size.splitn(2, 'x');
+    let x = iter.next()?.trim().parse().ok()?;
+    let y = iter.next()?.trim().parse().ok()?;
+    Some((x, y))
+}
+
+/// Size of a monitor on screen, after rotation and scaling.
+pub fn screen_size(config: &MonitorConfig) -> DResult<(isize, isize)> {
+    if let (Some(ref scale_from), Some(scale)) = (&config.scale_from, config.scale) {
+        return Err(Error::Placement(format!(
+            "{}: scale {} conflicts with scale_from {}", label(config), scale, scale_from)));
+    }
+    if config.transform.is_some() && (config.scale.is_some() || config.scale_from.is_some()) {
+        return Err(Error::Placement(format!("{}: transform conflicts with scaling", label(config))));
+    }
+    if let Some(ref panning) = config.panning {
+        return Ok((panning.area.width, panning.area.height));
+    }
+    if let Some(ref scale_from) = config.scale_from {
+        return parse_size(scale_from)
+            .ok_or_else(|| Error::Placement(format!("{}: cannot read scale_from {}", label(config), scale_from)));
+    }
+    let mode = config.mode.as_ref()
+        .ok_or_else(|| Error::Placement(format!("{}: has no mode", label(config))))?;
+    let size = Geometry::rotate((mode.width as isize, mode.height as isize), &config.orientation);
+    Ok(match (config.scale, config.transform) {
+        (Some(scale), _) => ((size.0 as f64 * scale).round() as isize, (size.1 as f64 * scale).round() as isize),
+        (None, Some(transform)) => transform.bounds(size),
+        (None, None) => size,
+    })
+}
+
+fn label(config: &MonitorConfig) -> &str {
+    config.connector.as_ref().unwrap_or(&config.display_name)
+}
+
+/// Describes the constraint that placed a monitor, for error messages.
+fn constraint(config: &MonitorConfig) -> String {
+    match config.relation() {
+        Ok(Some((side, target))) => format!("{} {} {}", label(config), side, target),
+        _ => format!("{} at {}", label(config), config.position.unwrap_or_default()),
+    }
+}
+
+impl MonitorConfig {
+    /// The monitor this one is placed next to, if it uses relative placement.
+    pub fn relation(&self) -> DResult<Option<(Side, &str)>> {
+        let mut relations = Side::ALL.iter().filter_map(|&side| self.relative_to(side).map(|x| (side, x)));
+        let first = relations.next();
+        if let (Some(a), Some(b)) = (first, relations.next()) {
+            return Err(Error::Placement(format!(
+                "{}: {} {} conflicts with {} {}", label(self), a.0, a.1, b.0, b.1)));
+        }
+        Ok(first)
+    }
+
+    fn relative_to(&self, side: Side) -> Option<&str> {
+        match side {
+            Side::RightOf => self.right_of.as_deref(),
+            Side::LeftOf => self.left_of.as_deref(),
+            Side::Above => self.above.as_deref(),
+            Side::Below => self.below.as_deref(),
+            Side::SameAs => self.same_as.as_deref(),
+        }
+    }
+
+    /// Places the monitor next to `target`, replacing any earlier placement.
+    pub fn place(&mut self, side: Side, target: &str) {
+        self.clear_placement();
+        let target = Some(target.to_string());
+        match side {
+            Side::RightOf => self.right_of = target,
+            Side::LeftOf => self.left_of = target,
+            Side::Above => self.above = target,
+            Side::Below => self.below = target,
+            Side::SameAs => self.same_as = target,
+        }
+    }
+
+    /// Removes all relative placement, so `position` is used.
+    pub fn clear_placement(&mut self) {
+        self.right_of = None;
+        self.left_of = None;
+        self.above = None;
+        self.below = None;
+        self.same_as = None;
+        self.position = None;
+    }
+}
+
+/// Computes the absolute position of every monitor in `configs`.
+///
+/// Relations refer to other monitors of the same list, so monitors that are
+/// not connected or turned off cannot be placed against. The result is
+/// shifted so that no coordinate is negative.
+pub fn solve(configs: &[&MonitorConfig]) -> DResult<Vec<(isize, isize)>> {
+    let mut sizes = Vec::new();
+    let mut relations = Vec::new();
+    let mut positions = Vec::new();
+    for config in configs {
+        sizes.push(screen_size(config)?);
+        let relation = config.relation()?;
+        positions.push(match relation {
+            Some((side, target)) if config.position.is_some() => {
+                return Err(Error::Placement(format!(
+                    "{}: position {} conflicts with {} {}", label(config), config.position.unwrap(), side, target)));
+            }
+            Some(_) => None,
+            None => Some(config.position.unwrap_or_default().into()),
+        });
+        relations.push(relation);
+    }
+
+    let mut targets = Vec::new();
+    for (i, relation) in relations.iter().enumerate() {
+        targets.push(match *relation {
+            Some((side, target)) => Some(
+                (0..configs.len())
+                    .find(|&j| j != i && configs[j].selected_by(target))
+                    .ok_or_else(|| Error::Placement(format!(
+                        "{} {} {}, which is not connected or turned off", label(configs[i]), side, target)))?
+            ),
+            None => None,
+        });
+    }
+
+    loop {
+        let mut progress = false;
+        for i in 0..configs.len() {
+            let j = match targets[i] {
+                Some(j) if positions[i].is_none() => j,
+                _ => continue,
+            };
+            if let Some(reference) = positions[j] {
+                let side = relations[i].unwrap().0;
+                positions[i] = Some(place(configs[i], side, reference, sizes[j], sizes[i])?);
+                progress = true;
+            }
+        }
+        if !progress {
+            break;
+        }
+    }
+
+    let unsolved: Vec<String> = (0..configs.len())
+        .filter(|&i| positions[i].is_none())
+        .map(|i| constraint(configs[i]))
+        .collect();
+    if !unsolved.is_empty() {
+        return Err(Error::Placement(format!("circular placement: {}", unsolved.join(", "))));
+    }
+    let mut positions: Vec<(isize, isize)> = positions.into_iter().map(Option::unwrap).collect();
+
+    // Monitors at the same absolute position are mirrored on purpose, but
+    // relative placement should never stack monitors on top of each other
+    let mirrored = |i: usize| relations[i].is_some_and(|x| x.0 == Side::SameAs);
+    for i in 0..configs.len() {
+        for j in i + 1..configs.len() {
+            if (relations[i].is_some() || relations[j].is_some()) && !mirrored(i) && !mirrored(j) {
+                let (a, b) = (positions[i], positions[j]);
+                if a.0 < b.0 + sizes[j].0 && b.0 < a.0 + sizes[i].0
+                    && a.1 < b.1 + sizes[j].1 && b.1 < a.1 + sizes[i].1 {
+                    return Err(Error::Placement(format!(
+                        "{} overlaps {}", constraint(configs[i]), constraint(configs[j]))));
+                }
+            }
+        }
+    }
+
+    let min_x = positions.iter().map(|x| x.0).min().unwrap_or(0).min(0);
+    let min_y = positions.iter().map(|x| x.1).min().unwrap_or(0).min(0);
+    for position in &mut positions {
+        position.0 -= min_x;
+        position.1 -= min_y;
+    }
+
+    Ok(positions)
+}
+
+/// Moves monitors at absolute `positions` after their sizes change from
+/// `old` to `new`.
+///
+/// A monitor to the right of or below others, overlapping them on the other
+/// axis, keeps its distance to their far edge, so touching monitors keep
+/// touching. Other monitors stay where they are.
+pub fn reflow(positions: &[(isize, isize)], old: &[(isize, isize)], new: &[(isize, isize)]) -> Vec<(isize, isize)> {
+    let xs = reflow_axis(positions, old, new, |p| p.0, |p| p.1);
+    let ys = reflow_axis(positions, old, new, |p| p.1, |p| p.0);
+    xs.into_iter().zip(ys).collect()
+}
+
+fn reflow_axis<F, G>(positions: &[(isize, isize)], old: &[(isize, isize)], new: &[(isize, isize)],
+                     along: F, across: G) -> Vec<isize>
+    where F: Fn((isize, isize)) -> isize, G: Fn((isize, isize)) -> isize
+{
+    let mut order: Vec<usize> = (0..positions.len()).collect();
+    order.sort_by_key(|&i| along(positions[i]));
+
+    let mut result: Vec<isize> = positions.iter().map(|&p| along(p)).collect();
+    for (n, &i) in order.iter().enumerate() {
+        let start = along(positions[i]);
+        let before = order[..n].iter().filter(|&&j| {
+            along(positions[j]) + along(old[j]) <= start
+                && across(positions[j]) < across(positions[i]) + across(old[i])
+                && across(positions[i]) < across(positions[j]) + across(old[j])
+        });
+        if let Some(x) = before.map(|&j| result[j] + along(new[j]) + start - along(positions[j]) - along(old[j])).max() {
+            result[i] = x;
+        }
+    }
+    result
+}
+
+fn place(config: &MonitorConfig, side: Side, reference: (isize, isize), reference_size: (isize, isize),
+         size: (isize, isize)) -> DResult<(isize, isize)> {
+    if side == Side::SameAs {
+        return Ok(reference);
+    }
+    let align = config.align.unwrap_or(if side.is_horizontal() { Align::Top } else { Align::Left });
+    let aligned = match (side.is_horizontal(), align) {
+        (true, Align::Top) => reference.1,
+        (true, Align::Bottom) => reference.1 + reference_size.1 - size.1,
+        (true, Align::Center) => reference.1 + (reference_size.1 - size.1) / 2,
+        (false, Align::Left) => reference.0,
+        (false, Align::Right) => reference.0 + reference_size.0 - size.0,
+        (false, Align::Center) => reference.0 + (reference_size.0 - size.0) / 2,
+        _ => {
+            return Err(Error::Placement(format!(
+                "{}: align {} conflicts with {}", label(config), align.name(), side)));
+        }
+    } + config.offset;
+
+    Ok(match side {
+        Side::RightOf => (reference.0 + reference_size.0, aligned),
+        Side::LeftOf => (reference.0 - size.0, aligned),
+        Side::Above => (aligned, reference.1 - size.1),
+        Side::Below => (aligned, reference.1 + reference_size.1),
+        Side::SameAs => reference,
+    })
+}
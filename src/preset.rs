@@ -0,0 +1,207 @@
+//! Layouts generated from the connected monitors.
+//!
+//! Presets cover hardware that has no saved profile yet. Every connected
+//! monitor gets its preferred mode, and the monitors are arranged by the
+//! relative placement of the `placement` module, so the positions follow
+//! from the modes.
+
+use std::cmp::Ordering;
+use std::fmt;
+
+use mode::Position;
+use placement::{Align, Side};
+use {DResult, Error, ModeInfo, MonitorConfig, Output, Profile};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Preset {
+    /// All monitors side by side, left to right.
+    ExtendRow,
+    /// All monitors on top of each other, top to bottom.
+    Stack,
+    /// All monitors showing the same picture of the first one, in the best
+    /// mode they have in common or scaled to their own.
+    Mirror,
+    /// Only the built-in panel.
+    InternalOnly,
+    /// All monitors except the built-in panel, side by side.
+    ExternalsOnly,
+}
+
+impl Preset {
+    pub const ALL: [Preset; 5] = [
+        Preset::ExtendRow,
+        Preset::Stack,
+        Preset::Mirror,
+        Preset::InternalOnly,
+        Preset::ExternalsOnly,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Preset::ExtendRow => "extend-row",
+            Preset::Stack => "stack",
+            Preset::Mirror => "mirror",
+            Preset::InternalOnly => "internal-only",
+            Preset::ExternalsOnly => "externals-only",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Preset> {
+        Preset::ALL.iter().cloned().find(|x| x.name() == name)
+    }
+
+    fn includes(self, output: &Output) -> bool {
+        match self {
+            Preset::InternalOnly => output.is_internal(),
+            Preset::ExternalsOnly => !output.is_internal(),
+            _ => true,
+        }
+    }
+}
+
+impl fmt::Display for Preset {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A preset together with its options, as given on the command line or in
+/// the `auto` section of the config file.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PresetConfig {
+    pub preset: Preset,
+    /// Edge the monitors of a row or stack line up on.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub align: Option<Align>,
+    /// Monitors to put first, by connector, display name or serial. The
+    /// others follow in connector order.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub order: Vec<String>,
+}
+
+impl PresetConfig {
+    pub fn new(preset: Preset) -> PresetConfig {
+        PresetConfig { preset, align: None, order: Vec::new() }
+    }
+
+    /// Builds the layout of the preset for the connected monitors.
+    pub fn generate(&self, connected: &[Output]) -> DResult<Profile> {
+        let mut outputs: Vec<MonitorConfig> = connected
+            .iter()
+            .filter(|x| self.preset.includes(x))
+            .filter_map(|x| {
+                let mode = x.preferred_mode()?;
+                Some(MonitorConfig {
+                    display_name: x.name.clone(),
+                    edid: if x.edid.is_empty() { None } else { Some(x.edid.clone()) },
+                    connector: Some(x.connection_name.clone()),
+                    mode: Some(mode.mode()),
+                    enabled: true,
+                    ..MonitorConfig::default()
+                })
+            })
+            .collect();
+        if outputs.is_empty() {
+            return Err(Error::Preset(format!("{}: no matching monitor is connected", self.preset)));
+        }
+
+        // Stable, so the connector order is kept among unlisted monitors
+        let order = &self.order;
+        outputs.sort_by_key(|x| order.iter().position(|s| x.selected_by(s)).unwrap_or(order.len()));
+
+        match self.preset {
+            Preset::Stack => self.chain(&mut outputs, Side::Below),
+            Preset::Mirror => mirror(&mut outputs, connected),
+            _ => self.chain(&mut outputs, Side::RightOf),
+        }
+        outputs[0].primary = true;
+
+        Ok(Profile { outputs, ..Profile::default() })
+    }
+
+    /// Places every monitor on `side` of the one before it.
+    fn chain(&self, outputs: &mut [MonitorConfig], side: Side) {
+        outputs[0].position = Some(Position::default());
+        for i in 1..outputs.len() {
+            let previous = outputs[i - 1].connector.clone().unwrap_or_default();
+            outputs[i].place(side, &previous);
+            outputs[i].align = self.align;
+        }
+    }
+}
+
+/// Whether two modes have the same refresh rate, as far as xrandr rounds it.
+fn same_rate(a: &ModeInfo, b: &ModeInfo) -> bool {
+    (a.refresh - b.refresh).abs() < 0.05
+}
+
+fn same_size(a: &ModeInfo, b: &ModeInfo) -> bool {
+    a.width == b.width && a.height == b.height
+}
+
+fn by_refresh(a: &&ModeInfo, b: &&ModeInfo) -> Ordering {
+    a.refresh.partial_cmp(&b.refresh).unwrap_or(Ordering::Equal)
+}
+
+/// The mode every output should use to show the same picture, in the order
+/// of `outputs`.
+///
+/// The largest resolution they all support is used, at the fastest refresh
+/// rate they all share for it. Without a shared rate, each output uses its
+/// own fastest rate for that resolution.
+pub fn common_modes<'a>(outputs: &[&'a Output]) -> Option<Vec<&'a ModeInfo>> {
+    let source = outputs.first()?;
+
+    let size = source.modes
+        .iter()
+        .filter(|m| outputs.iter().all(|x| x.modes.iter().any(|n| same_size(m, n))))
+        .max_by_key(|x| x.area())?;
+
+    let shared = source.modes
+        .iter()
+        .filter(|m| same_size(size, m))
+        .filter(|m| outputs.iter().all(|x| x.modes.iter().any(|n| same_size(m, n) && same_rate(m, n))))
+        .max_by(by_refresh);
+    if let Some(mode) = shared {
+        return outputs
+            .iter()
+            .map(|x| x.modes.iter().find(|n| same_size(mode, n) && same_rate(mode, n)))
+            .collect();
+    }
+
+    outputs
+        .iter()
+        .map(|x| x.modes.iter().filter(|n| same_size(size, n)).max_by(by_refresh))
+        .collect()
+}
+
+/// Makes all monitors show the picture of the first one.
+///
+/// Without a common mode, the first monitor keeps its preferred mode and
+/// the others scale it to theirs with `--scale-from`.
+fn mirror(outputs: &mut [MonitorConfig], connected: &[Output]) {
+    let chosen: Vec<&Output> = outputs
+        .iter()
+        .filter_map(|c| connected.iter().find(|x| c.connector.as_ref() == Some(&x.connection_name)))
+        .collect();
+
+    outputs[0].position = Some(Position::default());
+    match common_modes(&chosen) {
+        Some(modes) => {
+            for (config, mode) in outputs.iter_mut().zip(modes) {
+                config.mode = Some(mode.mode());
+                config.rate = if mode.refresh > 0.0 { Some(mode.refresh) } else { None };
+                config.position = Some(Position::default());
+            }
+        }
+        None => {
+            let source = outputs[0].connector.clone().unwrap_or_default();
+            let size = outputs[0].mode.as_ref().map(|x| format!("{}x{}", x.width, x.height));
+            for config in &mut outputs[1..] {
+                config.place(Side::SameAs, &source);
+                config.scale_from = size.clone();
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Policies choosing the refresh rate when a profile is applied.
+//!
+//! ~~~yaml
+//! refresh: max
+//! outputs:
+//!   - display_name: XG2431
+//!     refresh:
+//!       on_battery: 60
+//!       on_ac: max
+//! ~~~
+//!
+//! The rate is picked among the modes the monitor lists for the chosen mode:
+//! `max` takes the fastest, `preferred` the one the monitor prefers and a
+//! number the closest one. `saved`, the default, uses `rate` or the rate in
+//! `mode` as before. `on_battery` and `on_ac` depend on the power supply,
+//! read like the `ac` condition. A policy of an output wins over the one of
+//! the profile, and neither applies to modes created from a `modeline`.
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Serialize, Serializer};
+
+use ModeInfo;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(try_from = "RateValue")]
+pub enum Rate {
+    #[default]
+    Saved,
+    Max,
+    Preferred,
+    Hz(f64),
+}
+
+/// A rate as written in a config file, a name or a number.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RateValue {
+    Hz(f64),
+    Name(String),
+}
+
+impl Rate {
+    pub fn from_name(name: &str) -> Option<Rate> {
+        match name {
+            "saved" => Some(Rate::Saved),
+            "max" => Some(Rate::Max),
+            "preferred" => Some(Rate::Preferred),
+            _ => None,
+        }
+    }
+
+    /// The mode the policy picks among `modes`, all of the same name, or
+    /// none to keep the saved rate.
+    pub fn choose<'a>(&self, modes: &[&'a ModeInfo]) -> Option<&'a ModeInfo> {
+        match *self {
+            Rate::Saved => None,
+            Rate::Max => modes.iter().cloned().max_by(|a, b| a.refresh.total_cmp(&b.refresh)),
+            // xrandr lists the preferred rate of a size first
+            Rate::Preferred => modes.iter().cloned().find(|x| x.preferred).or_else(|| modes.first().cloned()),
+            Rate::Hz(hz) => modes.iter().cloned().min_by(|a, b| (a.refresh - hz).abs().total_cmp(&(b.refresh - hz).abs())),
+        }
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Rate::Saved => write!(f, "saved"),
+            Rate::Max => write!(f, "max"),
+            Rate::Preferred => write!(f, "preferred"),
+            Rate::Hz(hz) => write!(f, "{}", hz),
+        }
+    }
+}
+
+impl FromStr for Rate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Rate, String> {
+        let s = s.trim();
+        Rate::from_name(s)
+            .or_else(|| s.parse().ok().filter(|&x: &f64| x > 0.0).map(Rate::Hz))
+            .ok_or_else(|| format!("expected max, saved, preferred or a rate in Hz, got {}", s))
+    }
+}
+
+impl TryFrom<RateValue> for Rate {
+    type Error = String;
+
+    fn try_from(value: RateValue) -> Result<Rate, String> {
+        match value {
+            RateValue::Hz(hz) if hz > 0.0 => Ok(Rate::Hz(hz)),
+            RateValue::Hz(hz) => Err(format!("expected a positive rate, got {}", hz)),
+            RateValue::Name(name) => name.parse(),
+        }
+    }
+}
+
+impl Serialize for Rate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Rate::Hz(hz) => serializer.serialize_f64(hz),
+            _ => serializer.collect_str(self),
+        }
+    }
+}
+
+/// The rate policy of a profile or output, which may depend on the power
+/// supply.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Refresh {
+    Always(Rate),
+    Power {
+        #[serde(default)]
+        on_battery: Rate,
+        #[serde(default)]
+        on_ac: Rate,
+    },
+}
+
+impl Refresh {
+    /// The policy on AC power or on battery.
+    pub fn rate(&self, ac: bool) -> Rate {
+        match *self {
+            Refresh::Always(rate) => rate,
+            Refresh::Power { on_ac, .. } if ac => on_ac,
+            Refresh::Power { on_battery, .. } => on_battery,
+        }
+    }
+
+    /// The policy with the power supply decided.
+    pub fn on_power(&self, ac: bool) -> Refresh {
+        Refresh::Always(self.rate(ac))
+    }
+}
+
+impl fmt::Display for Refresh {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Refresh::Always(rate) => write!(f, "{}", rate),
+            Refresh::Power { on_battery, on_ac } => write!(f, "on_battery: {}, on_ac: {}", on_battery, on_ac),
+        }
+    }
+}
+
+impl FromStr for Refresh {
+    type Err = String;
+
+    /// Reads a rate, or both as `on_battery: 60, on_ac: max`.
+    fn from_str(s: &str) -> Result<Refresh, String> {
+        if !s.contains(':') {
+            return s.parse().map(Refresh::Always);
+        }
+        let (mut on_battery, mut on_ac) = (Rate::Saved, Rate::Saved);
+        for part in s.split(',') {
+            match part.split_once(':').map(|(key, value)| (key.trim(), value)) {
+                Some(("on_battery", value)) => on_battery = value.parse()?,
+                Some(("on_ac", value)) => on_ac = value.parse()?,
+                _ => return Err(format!("expected on_battery: <rate>, on_ac: <rate>, got {}", s)),
+            }
+        }
+        Ok(Refresh::Power { on_battery, on_ac })
+    }
+}
@@ -0,0 +1,158 @@
+//! Patterns that match monitors nobody has saved yet.
+//!
+//! A monitor of a profile with a `match` rule stands for every connected
+//! output the rule accepts, instead of one monitor with a known EDID. All
+//! given fields have to match:
+//!
+//! ~~~yaml
+//! match:
+//!   vendor: DEL
+//!   model: "DELL U24*"
+//!   serial: "^7"
+//!   connector: "DP*"
+//!   external: true
+//! ~~~
+//!
+//! `vendor`, `model` and `connector` are globs with `*` and `?`, compared
+//! without regard to case for the vendor. `serial` is a regular expression.
+//!
+//! A `role` picks a monitor by what it is used for instead, the built-in
+//! panel of a laptop (`internal`), any other monitor (`external`), or the
+//! n-th other monitor in connector order (`external[0]`).
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use regex::{Regex, RegexBuilder};
+
+use {DResult, Error, Output};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum Role {
+    Internal,
+    /// Any external monitor, or the one with the given index.
+    External(Option<usize>),
+}
+
+impl Role {
+    /// Whether the role accepts `output`, which is the `external_index`-th
+    /// external monitor if it is not internal.
+    pub fn accepts(self, output: &Output, external_index: Option<usize>) -> bool {
+        match self {
+            Role::Internal => output.is_internal(),
+            Role::External(None) => !output.is_internal(),
+            Role::External(Some(n)) => external_index == Some(n),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Role::Internal => write!(f, "internal"),
+            Role::External(None) => write!(f, "external"),
+            Role::External(Some(n)) => write!(f, "external[{}]", n),
+        }
+    }
+}
+
+impl TryFrom<String> for Role {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Role, String> {
+        match &s[..] {
+            "internal" => return Ok(Role::Internal),
+            "external" => return Ok(Role::External(None)),
+            _ => (),
+        }
+        s.strip_prefix("external[")
+            .and_then(|x| x.strip_suffix(']'))
+            .and_then(|x| x.parse().ok())
+            .map(|n| Role::External(Some(n)))
+            .ok_or_else(|| format!("unknown role {}, expected internal, external or external[N]", s))
+    }
+}
+
+impl From<Role> for String {
+    fn from(role: Role) -> String {
+        role.to_string()
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MatchRule {
+    /// Three letter PNP id of the manufacturer, from the EDID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vendor: Option<String>,
+    /// Monitor name from the EDID.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+    /// Port name like `HDMI*`, `DP*` or `eDP*`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connector: Option<String>,
+    /// Only matches outputs other than the built-in panel if true, or only
+    /// the built-in panel if false.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external: Option<bool>,
+}
+
+/// Translates a glob to an anchored regular expression.
+pub(crate) fn glob(pattern: &str, case_insensitive: bool) -> Result<Regex, regex::Error> {
+    let mut re = String::from("^");
+    for c in pattern.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            c => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    RegexBuilder::new(&re).case_insensitive(case_insensitive).build()
+}
+
+fn invalid(field: &str, pattern: &str, e: regex::Error) -> Error {
+    Error::InvalidProfile(format!("{} pattern {}: {}", field, pattern, e))
+}
+
+impl MatchRule {
+    /// Checks that all patterns can be compiled.
+    pub fn validate(&self) -> DResult<()> {
+        self.compile().map(|_| ())
+    }
+
+    fn compile(&self) -> DResult<[Option<Regex>; 4]> {
+        let compile_glob = |field: &str, pattern: &Option<String>, case_insensitive| match *pattern {
+            Some(ref x) => glob(x, case_insensitive).map(Some).map_err(|e| invalid(field, x, e)),
+            None => Ok(None),
+        };
+        let serial = match self.serial {
+            Some(ref x) => Some(Regex::new(x).map_err(|e| invalid("serial", x, e))?),
+            None => None,
+        };
+        Ok([
+            compile_glob("vendor", &self.vendor, true)?,
+            compile_glob("model", &self.model, false)?,
+            serial,
+            compile_glob("connector", &self.connector, false)?,
+        ])
+    }
+
+    /// Whether the rule accepts the connected output. Invalid patterns never match.
+    pub fn matches(&self, output: &Output) -> bool {
+        let [vendor, model, serial, connector] = match self.compile() {
+            Ok(x) => x,
+            Err(_) => return false,
+        };
+        let monitor = output.monitor();
+        let model_name = monitor.as_ref().map(|x| x.model.as_str()).unwrap_or(&output.name);
+
+        vendor.is_none_or(|re| monitor.as_ref().is_some_and(|x| re.is_match(&x.vendor)))
+            && model.is_none_or(|re| re.is_match(model_name))
+            && serial.is_none_or(|re| monitor.as_ref().and_then(|x| x.serial.as_ref()).is_some_and(|x| re.is_match(x)))
+            && connector.is_none_or(|re| re.is_match(&output.connection_name))
+            && self.external.is_none_or(|x| x != output.is_internal())
+    }
+}
@@ -0,0 +1,252 @@
+//! Reflection, transformation matrices and panning of outputs.
+//!
+//! ~~~yaml
+//! reflect: x
+//! transform: "1.08,0.05,-90,0,1.1,-50,0,0.00005,1"
+//! panning: 3840x2160/1920x1080+0+0
+//! ~~~
+//!
+//! `transform` is the 3x3 matrix of `xrandr --transform`, row by row, for
+//! example to correct the keystone of a projector. `panning` is the area the
+//! picture pans across, optionally followed by the tracking area and the
+//! border, as in `xrandr --panning`. Its position comes from the placement of
+//! the monitor.
+//!
+//! All of them are read from the output of xrandr when a layout is saved, a
+//! plain scale as `scale` or `scale_from`.
+
+use std::convert::TryFrom;
+use std::fmt;
+
+use Filter;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Reflect {
+    X,
+    Y,
+    XY,
+}
+
+impl Reflect {
+    pub fn from_name(name: &str) -> Option<Reflect> {
+        match name {
+            "x" => Some(Reflect::X),
+            "y" => Some(Reflect::Y),
+            "xy" => Some(Reflect::XY),
+            _ => None,
+        }
+    }
+
+    /// The name of `xrandr --reflect`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Reflect::X => "x",
+            Reflect::Y => "y",
+            Reflect::XY => "xy",
+        }
+    }
+}
+
+/// A projective transformation of the picture, see `xrandr --transform`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Transform(pub [f64; 9]);
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform([1.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0]);
+
+    pub fn from_values(values: &[f64]) -> Option<Transform> {
+        if values.len() != 9 {
+            return None;
+        }
+        let mut matrix = [0.0; 9];
+        matrix.copy_from_slice(values);
+        Some(Transform(matrix))
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.0.iter().zip(&Transform::IDENTITY.0).all(|(a, b)| (a - b).abs() < 1e-6)
+    }
+
+    /// The horizontal and vertical factor, if the matrix does nothing but
+    /// scale.
+    pub fn as_scale(&self) -> Option<(f64, f64)> {
+        let m = &self.0;
+        let zero = [m[1], m[2], m[3], m[5], m[6], m[7]].iter().all(|x| x.abs() < 1e-6);
+        if zero && (m[8] - 1.0).abs() < 1e-6 { Some((m[0], m[4])) } else { None }
+    }
+
+    /// Size of the screen area a picture of `size` pixels covers, the
+    /// bounding box of its transformed corners.
+    pub fn bounds(&self, size: (isize, isize)) -> (isize, isize) {
+        let m = &self.0;
+        let (w, h) = (size.0 as f64, size.1 as f64);
+        let corners: Vec<(f64, f64)> = [(0.0, 0.0), (w, 0.0), (0.0, h), (w, h)]
+            .iter()
+            .map(|&(x, y)| {
+                let z = m[6] * x + m[7] * y + m[8];
+                ((m[0] * x + m[1] * y + m[2]) / z, (m[3] * x + m[4] * y + m[5]) / z)
+            })
+            .collect();
+        let extent = |f: &dyn Fn(&(f64, f64)) -> f64| {
+            let max = corners.iter().map(f).fold(f64::MIN, f64::max);
+            let min = corners.iter().map(f).fold(f64::MAX, f64::min);
+            (max - min).round() as isize
+        };
+        (extent(&|c| c.0), extent(&|c| c.1))
+    }
+}
+
+impl fmt::Display for Transform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let values: Vec<String> = self.0.iter().map(|x| x.to_string()).collect();
+        write!(f, "{}", values.join(","))
+    }
+}
+
+impl TryFrom<String> for Transform {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Transform, String> {
+        let values: Result<Vec<f64>, _> = s.split(',').map(|x| x.trim().parse()).collect();
+        values.ok()
+            .and_then(|x| Transform::from_values(&x))
+            .ok_or_else(|| format!("expected 9 comma separated numbers as transform, got {}", s))
+    }
+}
+
+impl From<Transform> for String {
+    fn from(transform: Transform) -> String {
+        transform.to_string()
+    }
+}
+
+/// A rectangle as `WxH+X+Y`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Area {
+    pub width: isize,
+    pub height: isize,
+    pub x: isize,
+    pub y: isize,
+}
+
+impl Area {
+    fn parse(s: &str) -> Option<Area> {
+        let mut iter = s.split(&['x', '+'][..]).map(|x| x.parse().ok());
+        let width = iter.next()??;
+        let height = iter.next()??;
+        let (x, y) = match (iter.next(), iter.next(), iter.next()) {
+            (None, None, None) => (0, 0),
+            (Some(x), Some(y), None) => (x?, y?),
+            _ => return None,
+        };
+        Some(Area { width, height, x, y })
+    }
+}
+
+impl fmt::Display for Area {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}+{}+{}", self.width, self.height, self.x, self.y)
+    }
+}
+
+/// The area a monitor pans across, see `xrandr --panning`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Panning {
+    pub area: Area,
+    /// Where the mouse pointer moves the picture, the whole area if not given.
+    pub tracking: Option<Area>,
+    /// Distance to the edges of the picture at which panning starts, as left,
+    /// top, right and bottom.
+    pub border: Option<[isize; 4]>,
+}
+
+impl Panning {
+    /// The panning area placed at `position`.
+    pub fn at(&self, position: (isize, isize)) -> Panning {
+        Panning { area: Area { x: position.0, y: position.1, ..self.area }, ..*self }
+    }
+}
+
+impl fmt::Display for Panning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.area)?;
+        if self.tracking.is_some() || self.border.is_some() {
+            write!(f, "/{}", self.tracking.unwrap_or(self.area))?;
+        }
+        if let Some(border) = self.border {
+            write!(f, "/{}/{}/{}/{}", border[0], border[1], border[2], border[3])?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<String> for Panning {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Panning, String> {
+        let error = || format!("expected WxH[+X+Y[/WxH+X+Y[/L/T/R/B]]] as panning, got {}", s);
+        let parts: Vec<&str> = s.split('/').collect();
+        let area = Area::parse(parts[0]).ok_or_else(error)?;
+        let tracking = match parts.get(1) {
+            Some(x) => Some(Area::parse(x).ok_or_else(error)?),
+            None => None,
+        };
+        let border = match parts.len() {
+            1 | 2 => None,
+            6 => {
+                let mut border = [0; 4];
+                for (x, part) in border.iter_mut().zip(&parts[2..]) {
+                    *x = part.parse().map_err(|_| error())?;
+                }
+                Some(border)
+            }
+            _ => return Err(error()),
+        };
+        Ok(Panning { area, tracking, border })
+    }
+}
+
+impl From<Panning> for String {
+    fn from(panning: Panning) -> String {
+        panning.to_string()
+    }
+}
+
+/// How xrandr currently transforms the picture of an output.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Transformation {
+    pub reflect: Option<Reflect>,
+    /// The matrix, unless it is the identity.
+    pub transform: Option<Transform>,
+    pub filter: Option<Filter>,
+    pub panning: Option<Panning>,
+}
+
+/// Reads the reflection and panning from the header of an output, after its
+/// name and state, as in
+/// `1920x1080+0+0 (0x48) normal X axis (normal left ...) 520mm x 320mm panning ...`.
+pub fn parse_header(header: &[&str]) -> (Option<Reflect>, Option<Panning>) {
+    // The reflection follows the rotation, before the supported rotations
+    let current: Vec<&str> = header.iter().cloned().take_while(|x| !x.starts_with("(n")).collect();
+    let reflect = match (current.contains(&"X"), current.contains(&"Y")) {
+        (true, true) => Some(Reflect::XY),
+        (true, false) => Some(Reflect::X),
+        (false, true) => Some(Reflect::Y),
+        (false, false) => None,
+    };
+
+    let after = |key: &str| header.iter().position(|x| *x == key).and_then(|i| header.get(i + 1));
+    let area = after("panning").and_then(|x| Area::parse(x)).filter(|x| x.width > 0 && x.height > 0);
+    let panning = area.map(|area| {
+        let tracking = after("tracking").and_then(|x| Area::parse(x)).filter(|x| *x != area);
+        let border: Option<Vec<isize>> = after("border").and_then(|x| x.split('/').map(|x| x.parse().ok()).collect());
+        let border = border
+            .filter(|x| x.len() == 4 && x.iter().any(|&x| x != 0))
+            .map(|x| [x[0], x[1], x[2], x[3]]);
+        Panning { area, tracking: tracking.or(if border.is_some() { Some(area) } else { None }), border }
+    });
+    (reflect, panning)
+}
@@ -0,0 +1,487 @@
+//! In-place updates of hand-written yaml files.
+//!
+//! Instead of regenerating a whole file, what the existing text stands for
+//! is compared with the new value and only the lines of changed entries are
+//! rewritten.
+//! Comments, blank lines, quoting and key order of everything else stay as
+//! they are. Only the block style subset of yaml that people usually write
+//! by hand is understood; for anything else, like multi-line strings or
+//! anchors, `Document::parse` fails and the caller regenerates the file.
+
+use serde_yaml::{Mapping, Value};
+
+#[derive(Debug)]
+enum Node {
+    /// A key without any value.
+    Null,
+    /// A value on the same line as its key or dash, `lines[line][start..end]`.
+    Inline { line: usize, start: usize, end: usize },
+    Map(Vec<Entry>),
+    Seq(Vec<Item>),
+}
+
+#[derive(Debug)]
+struct Entry {
+    key: String,
+    rename: Option<String>,
+    /// First line, including the comments in front of the key.
+    start: usize,
+    /// Line and columns of the key itself.
+    line: usize,
+    col: usize,
+    key_end: usize,
+    value: Node,
+    end: usize,
+}
+
+#[derive(Debug)]
+struct Item {
+    start: usize,
+    line: usize,
+    /// Column of the dash.
+    col: usize,
+    value: Node,
+    end: usize,
+}
+
+/// A parsed yaml document that can be updated to a new value.
+#[derive(Debug)]
+pub struct Document {
+    lines: Vec<String>,
+    /// Number of lines before the root mapping, e.g. a `---` marker.
+    header: usize,
+    root: Vec<Entry>,
+    /// First line after the root mapping.
+    end: usize,
+}
+
+fn is_trivia(line: &str) -> bool {
+    let trimmed = line.trim_start();
+    trimmed.is_empty() || trimmed.starts_with('#')
+}
+
+fn indent_of(line: &str) -> usize {
+    line.len() - line.trim_start_matches(' ').len()
+}
+
+fn is_dash(text: &str) -> bool {
+    text == "-" || text.starts_with("- ")
+}
+
+/// Returns the end of `text` without a trailing comment or whitespace.
+fn value_end(text: &str) -> usize {
+    let bytes = text.as_bytes();
+    let mut quote = None;
+    let mut end = text.len();
+    for (i, &c) in bytes.iter().enumerate() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(b'"') if c == b'\\' => (),
+            Some(_) => (),
+            None if c == b'"' || c == b'\'' => quote = Some(c),
+            None if c == b'#' && (i == 0 || bytes[i - 1] == b' ') => {
+                end = i;
+                break;
+            }
+            None => (),
+        }
+    }
+    text[..end].trim_end().len()
+}
+
+/// Splits `key: rest` into the unquoted key, the end of the key token and
+/// the start of the rest.
+fn split_key(text: &str) -> Option<(String, usize, usize)> {
+    let (key, key_end) = if text.starts_with('"') || text.starts_with('\'') {
+        let quote = text.as_bytes()[0];
+        let close = text[1..].find(quote as char)? + 1;
+        (unquote(&text[..=close]), close + 1)
+    } else {
+        let colon = text
+            .match_indices(':')
+            .map(|x| x.0)
+            .find(|&i| text[i + 1..].is_empty() || text[i + 1..].starts_with(' '))?;
+        let key = text[..colon].trim_end();
+        if key.is_empty() || key.starts_with('#') || key.contains(" #") {
+            return None;
+        }
+        (key.to_string(), key.len())
+    };
+
+    let rest = &text[key_end..];
+    let rest_trimmed = rest.trim_start();
+    if !rest_trimmed.starts_with(':') {
+        return None;
+    }
+    let after_colon = key_end + (rest.len() - rest_trimmed.len()) + 1;
+    let value = &text[after_colon..];
+    Some((key, key_end, after_colon + (value.len() - value.trim_start().len())))
+}
+
+fn unquote(text: &str) -> String {
+    let text = text.trim();
+    if text.len() >= 2 && text.starts_with('"') && text.ends_with('"') {
+        text[1..text.len() - 1].replace("\\\"", "\"").replace("\\\\", "\\")
+    } else if text.len() >= 2 && text.starts_with('\'') && text.ends_with('\'') {
+        text[1..text.len() - 1].replace("''", "'")
+    } else {
+        text.to_string()
+    }
+}
+
+/// Renders a value that fits on one line, like a scalar or `{}`.
+fn inline(value: &Value) -> Option<String> {
+    match *value {
+        Value::Mapping(ref x) if !x.is_empty() => return None,
+        Value::Sequence(ref x) if !x.is_empty() => return None,
+        _ => (),
+    }
+    let lines = generate(value, 0);
+    if lines.len() == 1 {
+        lines.into_iter().next()
+    } else {
+        None
+    }
+}
+
+/// Renders a value as block yaml, indented by `indent` spaces.
+fn generate(value: &Value, indent: usize) -> Vec<String> {
+    let text = serde_yaml::to_string(value).unwrap_or_default();
+    let text = text.strip_prefix("---\n").unwrap_or(&text);
+    text.lines()
+        .map(|x| format!("{}{}", " ".repeat(indent), x))
+        .collect()
+}
+
+fn generate_entry(key: &Value, value: &Value, indent: usize) -> Vec<String> {
+    let mut mapping = Mapping::new();
+    mapping.insert(key.clone(), value.clone());
+    generate(&Value::Mapping(mapping), indent)
+}
+
+/// Puts the dash of a sequence item in front of the first content line.
+fn add_dash(lines: &mut Vec<String>, col: usize) {
+    match lines.iter_mut().find(|x| !is_trivia(x)) {
+        Some(line) => line.replace_range(col..col + 2, "- "),
+        None => lines.push(format!("{}- {{}}", " ".repeat(col))),
+    }
+}
+
+impl Document {
+    pub fn parse(text: &str) -> Option<Document> {
+        let lines: Vec<String> = text.lines().map(|x| x.to_string()).collect();
+
+        let mut header = 0;
+        while header < lines.len() && (is_trivia(&lines[header]) || lines[header].trim_end() == "---") {
+            if lines[header].trim_end() == "---" {
+                header += 1;
+                break;
+            }
+            header += 1;
+        }
+        // Comments in front of the first key belong to it
+        while header > 0 && is_trivia(&lines[header - 1]) {
+            header -= 1;
+        }
+
+        let mut document = Document {
+            lines,
+            header,
+            root: Vec::new(),
+            end: header,
+        };
+
+        let first = match document.next_content(header) {
+            Some(x) => x,
+            None => return Some(document),
+        };
+        if indent_of(&document.lines[first]) != 0 {
+            return None;
+        }
+        let (root, end) = match document.parse_node(header, first, 0)? {
+            (Node::Map(root), end) => (root, end),
+            _ => return None,
+        };
+        if document.next_content(end).is_some() {
+            return None;
+        }
+        document.root = root;
+        document.end = end;
+        Some(document)
+    }
+
+    fn next_content(&self, from: usize) -> Option<usize> {
+        (from..self.lines.len()).find(|&i| !is_trivia(&self.lines[i]))
+    }
+
+    /// Parses the block whose first line is `line`, with content at `col`.
+    fn parse_node(&self, start: usize, line: usize, col: usize) -> Option<(Node, usize)> {
+        if is_dash(&self.lines[line][col..]) {
+            self.parse_seq(start, line, col)
+        } else {
+            self.parse_map(start, line, col)
+        }
+    }
+
+    /// Parses the value after a key or dash, starting at `lines[line][col..]`.
+    fn parse_value(&self, line: usize, col: usize, parent_col: usize, allow_seq: bool) -> Option<(Node, usize)> {
+        let text = &self.lines[line][col..];
+        let end = value_end(text);
+        if end > 0 {
+            let first = text.as_bytes()[0];
+            if b"|>&*!?%@`".contains(&first) {
+                return None;
+            }
+            if (first == b'{' || first == b'[') && !text[..end].ends_with(['}', ']']) {
+                return None;
+            }
+            return Some((Node::Inline { line, start: col, end: col + end }, line + 1));
+        }
+
+        match self.next_content(line + 1) {
+            Some(next) => {
+                let next_col = indent_of(&self.lines[next]);
+                if next_col > parent_col || (allow_seq && next_col == parent_col && is_dash(&self.lines[next][next_col..])) {
+                    self.parse_node(line + 1, next, next_col)
+                } else {
+                    Some((Node::Null, line + 1))
+                }
+            }
+            None => Some((Node::Null, line + 1)),
+        }
+    }
+
+    fn parse_map(&self, mut start: usize, mut line: usize, col: usize) -> Option<(Node, usize)> {
+        let mut entries = Vec::new();
+
+        loop {
+            let text = &self.lines[line][col..];
+            let (key, key_end, value_start) = split_key(text)?;
+            let (value, end) = self.parse_value(line, col + value_start, col, true)?;
+            entries.push(Entry {
+                key,
+                rename: None,
+                start,
+                line,
+                col,
+                key_end: col + key_end,
+                value,
+                end,
+            });
+
+            match self.next_content(end) {
+                Some(next) if indent_of(&self.lines[next]) == col && !is_dash(&self.lines[next][col..]) => {
+                    start = end;
+                    line = next;
+                }
+                Some(next) if indent_of(&self.lines[next]) > col => return None,
+                _ => return Some((Node::Map(entries), end)),
+            }
+        }
+    }
+
+    fn parse_seq(&self, mut start: usize, mut line: usize, col: usize) -> Option<(Node, usize)> {
+        let mut items = Vec::new();
+
+        loop {
+            let text = &self.lines[line][col..];
+            let (value, end) = if text.len() > 2 && split_key(&text[2..]).is_some() {
+                self.parse_map(line, line, col + 2)?
+            } else if text.len() > 2 {
+                self.parse_value(line, col + 2, col, false)?
+            } else {
+                self.parse_value(line, self.lines[line].len(), col, false)?
+            };
+            items.push(Item {
+                start,
+                line,
+                col,
+                value,
+                end,
+            });
+
+            match self.next_content(end) {
+                Some(next) if indent_of(&self.lines[next]) == col && is_dash(&self.lines[next][col..]) => {
+                    start = end;
+                    line = next;
+                }
+                Some(next) if indent_of(&self.lines[next]) > col => return None,
+                _ => return Some((Node::Seq(items), end)),
+            }
+        }
+    }
+
+    /// Renames the key `from` of the mapping at `path` to `to`.
+    pub fn rename_key(&mut self, path: &[&str], from: &str, to: &str) -> bool {
+        let mut entries = &mut self.root;
+        for key in path {
+            let entry = entries.iter_mut().find(|x| x.rename.as_ref().unwrap_or(&x.key) == key);
+            match entry.map(|x| &mut x.value) {
+                Some(&mut Node::Map(ref mut x)) => entries = x,
+                _ => return false,
+            }
+        }
+        // An entry that is overwritten by the rename goes away
+        if from != to {
+            entries.retain(|x| x.rename.as_ref().unwrap_or(&x.key) != to);
+        }
+        match entries.iter_mut().find(|x| x.rename.as_ref().unwrap_or(&x.key) == from) {
+            Some(entry) => {
+                entry.rename = Some(to.to_string());
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Returns the document text changed to represent `value`.
+    ///
+    /// `old` is what the text stood for, as it would be written out. Where it
+    /// equals `value`, the text is kept even if it is spelled differently,
+    /// like `1.50` or `+10+0`, and so are keys that were left out of `old`
+    /// for having their default value, like `outputs: []`, unless `value`
+    /// sets them. Without `old`, scalars are compared by what they parse to.
+    pub fn update(&self, old: Option<&Value>, value: &Value) -> Option<String> {
+        let mapping = match *value {
+            Value::Mapping(ref x) => x,
+            _ => return None,
+        };
+
+        let mut out: Vec<String> = self.lines[..self.header].to_vec();
+        self.render_map(&self.root, old.and_then(Value::as_mapping), mapping, 0, &mut out);
+        out.extend(self.lines[self.end..].iter().cloned());
+
+        let mut text = out.join("\n");
+        text.push('\n');
+        Some(text)
+    }
+
+    fn same(&self, node: &Node, old: Option<&Value>, new: &Value) -> bool {
+        if let Some(old) = old {
+            return old == new;
+        }
+        match (node, new) {
+            (&Node::Null, &Value::Null) => true,
+            (&Node::Inline { line, start, end }, _) => {
+                serde_yaml::from_str::<Value>(&self.lines[line][start..end]).is_ok_and(|x| x == *new)
+            }
+            (Node::Map(entries), Value::Mapping(mapping)) => {
+                entries.len() == mapping.len() && entries.iter().all(|e| {
+                    e.rename.is_none()
+                        && mapping
+                            .get(&Value::String(e.key.clone()))
+                            .is_some_and(|v| self.same(&e.value, None, v))
+                })
+            }
+            (Node::Seq(items), Value::Sequence(seq)) => {
+                items.len() == seq.len() && items.iter().zip(seq).all(|(i, v)| self.same(&i.value, None, v))
+            }
+            _ => false,
+        }
+    }
+
+    fn render_map(&self, entries: &[Entry], old: Option<&Mapping>, mapping: &Mapping, col: usize, out: &mut Vec<String>) {
+        for entry in entries {
+            let key = Value::String(entry.rename.clone().unwrap_or_else(|| entry.key.clone()));
+            let old_value = old.and_then(|x| x.get(&Value::String(entry.key.clone())));
+            match mapping.get(&key) {
+                Some(value) => self.render_entry(entry, &key, old_value, value, out),
+                // Left out for having its default value, which it still has
+                None if old.is_some() && old_value.is_none() => {
+                    out.extend(self.lines[entry.start..entry.end].iter().cloned());
+                }
+                None => (),
+            }
+        }
+
+        for (key, value) in mapping {
+            let existing = entries
+                .iter()
+                .any(|e| Value::String(e.rename.clone().unwrap_or_else(|| e.key.clone())) == *key);
+            if !existing {
+                out.extend(generate_entry(key, value, col));
+            }
+        }
+    }
+
+    fn render_entry(&self, entry: &Entry, key: &Value, old: Option<&Value>, value: &Value, out: &mut Vec<String>) {
+        out.extend(self.lines[entry.start..entry.line].iter().cloned());
+
+        let mut key_line = self.lines[entry.line].clone();
+        if entry.rename.is_some() {
+            let mut key_text = generate(key, 0).concat();
+            if key_text.is_empty() {
+                key_text = "~".to_string();
+            }
+            key_line.replace_range(entry.col..entry.key_end, &key_text);
+        }
+
+        if self.same(&entry.value, old, value) {
+            out.push(key_line);
+            out.extend(self.lines[entry.line + 1..entry.end].iter().cloned());
+            return;
+        }
+
+        match (&entry.value, value) {
+            (&Node::Inline { start, end, .. }, _) if inline(value).is_some() => {
+                let old_len = self.lines[entry.line].len();
+                let (start, end) = (start + key_line.len() - old_len, end + key_line.len() - old_len);
+                key_line.replace_range(start..end, &inline(value).unwrap_or_default());
+                out.push(key_line);
+            }
+            (Node::Map(entries), Value::Mapping(mapping)) if !mapping.is_empty() => {
+                out.push(key_line);
+                self.render_map(entries, old.and_then(Value::as_mapping), mapping, entries[0].col, out);
+            }
+            (Node::Seq(items), Value::Sequence(seq)) if !seq.is_empty() => {
+                out.push(key_line);
+                self.render_seq(items, old.and_then(Value::as_sequence), seq, out);
+            }
+            _ => out.extend(generate_entry(key, value, entry.col)),
+        }
+    }
+
+    /// Renders the items of a sequence that may have grown or shrunk.
+    ///
+    /// Items are kept in order where they are unchanged. Items that are gone
+    /// are dropped, new ones are inserted, and an item that matches neither
+    /// is changed into the next new value.
+    fn render_seq(&self, items: &[Item], old: Option<&Vec<Value>>, seq: &[Value], out: &mut Vec<String>) {
+        let old_at = |i: usize| old.and_then(|x| x.get(i));
+        let unchanged = |i: usize, value: &Value| self.same(&items[i].value, old_at(i), value);
+
+        let mut next = 0;
+        for (n, value) in seq.iter().enumerate() {
+            if let Some(i) = (next..items.len()).find(|&i| unchanged(i, value)) {
+                let item = &items[i];
+                out.extend(self.lines[item.start..item.end].iter().cloned());
+                next = i + 1;
+            } else if next < items.len() && !seq[n + 1..].iter().any(|x| unchanged(next, x)) {
+                self.render_item(&items[next], old_at(next), value, out);
+                next += 1;
+            } else {
+                out.extend(generate(&Value::Sequence(vec![value.clone()]), items[0].col));
+            }
+        }
+    }
+
+    fn render_item(&self, item: &Item, old: Option<&Value>, value: &Value, out: &mut Vec<String>) {
+        out.extend(self.lines[item.start..item.line].iter().cloned());
+
+        match (&item.value, value) {
+            (&Node::Inline { line, start, end }, _) if line == item.line && inline(value).is_some() => {
+                let mut text = self.lines[line].clone();
+                text.replace_range(start..end, &inline(value).unwrap_or_default());
+                out.push(text);
+            }
+            (Node::Map(entries), Value::Mapping(mapping)) if entries[0].line == item.line => {
+                let mut lines = Vec::new();
+                self.render_map(entries, old.and_then(Value::as_mapping), mapping, item.col + 2, &mut lines);
+                // The first entry shares its line with the dash
+                add_dash(&mut lines, item.col);
+                out.extend(lines);
+            }
+            _ => out.extend(generate(&Value::Sequence(vec![value.clone()]), item.col)),
+        }
+    }
+}
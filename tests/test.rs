@@ -132,3 +132,1177 @@ fn json() {
     quickrandr::parse_json(j).unwrap();
 
 }
+
+#[test]
+fn parse_identical_monitors() {
+    let (outputs, names) = quickrandr::parse_xrandr(EXAMPLE3);
+
+    assert_eq!(names, ["DP1", "DP2", "DP3", "HDMI1", "HDMI2", "HDMI3", "LVDS1", "VGA1"]);
+    assert_eq!(outputs.len(), 3);
+
+    let dp2 = &outputs[0];
+    let dp3 = &outputs[1];
+    assert_eq!(dp2.name, "DELL U2415");
+    assert_eq!(dp3.name, "DELL U2415");
+    assert_ne!(dp2.monitor().unwrap().serial, dp3.monitor().unwrap().serial);
+    assert_eq!(dp3.geometry.as_ref().unwrap().orientation, quickrandr::Orientation::Left);
+
+    let lvds = &outputs[2];
+    assert_eq!(lvds.name, "LP140WH2-TLF1");
+    assert!(lvds.geometry.is_none());
+}
+
+#[test]
+fn capture_and_plan() {
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs, output_names };
+
+    let profile = quickrandr::capture_profile(&state);
+    assert_eq!(profile.outputs.len(), 2);
+    assert_eq!(profile.outputs[1].mode, "1920x1200".parse().ok());
+
+    let plan = quickrandr::plan_profile(&profile, &state).unwrap();
+    assert_eq!(plan.to_string(),
+               "xrandr --output DP1 --off --output HDMI1 --off --output HDMI2 --off \
+                --output HDMI3 --off --output LVDS1 --off --output VGA1 --off\n\
+                xrandr --output DP3 --mode 1920x1200 --pos 0x0 --rotate left \
+                --output DP2 --mode 1920x1200 --pos 1200x352 --rotate normal\n");
+}
+
+#[test]
+fn legacy_layout_file() {
+    let profile = quickrandr::parse_profile(r#"
+- display_name: DELL U2415
+  mode: 1920x1200
+  position: 0x0
+  orientation: Normal
+  primary: true
+"#, quickrandr::format::Format::Yaml).unwrap();
+    assert_eq!(profile.outputs[0].position, "0x0".parse().ok());
+}
+
+#[test]
+fn database_management() {
+    let mut database = quickrandr::database::Database::default();
+    database.insert("home", quickrandr::Profile::default(), false).unwrap();
+    assert!(database.insert("home", quickrandr::Profile::default(), false).is_err());
+
+    database.copy("home", "work", false).unwrap();
+    database.rename("work", "office", false).unwrap();
+    assert!(database.rename("office", "home", false).is_err());
+    database.remove("home").unwrap();
+
+    let names: Vec<_> = database.profiles.keys().collect();
+    assert_eq!(names, ["office"]);
+}
+
+#[test]
+fn profile_inheritance() {
+    let database = quickrandr::database::Database::parse(r#"
+profiles:
+  desk:
+    outputs:
+      - display_name: LP140WH2-TLF1
+        connector: LVDS1
+        mode: 1366x768
+        position: 0x0
+        orientation: Normal
+        primary: false
+      - display_name: DELL U2415
+        connector: DP2
+        mode: 1920x1200
+        position: 1366x0
+        orientation: Normal
+        primary: true
+  desk-lid-closed:
+    extends: desk
+    overrides:
+      - output: LVDS1
+        enabled: false
+  review:
+    extends: desk-lid-closed
+    overrides:
+      - output: DELL U2415
+        orientation: Left
+        mode: 1920x1200
+  loop-a:
+    extends: loop-b
+  loop-b:
+    extends: loop-a
+"#, quickrandr::format::Format::Yaml).unwrap();
+
+    let review = database.resolve("review").unwrap();
+    assert!(review.extends.is_none());
+    assert!(!review.outputs[0].enabled);
+    assert_eq!(review.outputs[1].orientation, quickrandr::Orientation::Left);
+
+    // The parent itself stays untouched
+    assert!(database.resolve("desk").unwrap().outputs[0].enabled);
+
+    assert!(database.resolve("loop-a").is_err());
+
+    // Exported children stand on their own
+    let format = quickrandr::format::Format::Yaml;
+    let exported = database.export("review", format).unwrap();
+    let mut other = quickrandr::database::Database::default();
+    other.insert("review", quickrandr::parse_profile(&exported, format).unwrap(), false).unwrap();
+    let imported = other.resolve("review").unwrap();
+    assert!(!imported.outputs[0].enabled);
+    assert_eq!(imported.outputs[1].orientation, quickrandr::Orientation::Left);
+}
+
+#[test]
+fn layered_config() {
+    use std::fs;
+    use quickrandr::database::ConfigSources;
+
+    let root = std::env::temp_dir().join(format!("quickrandr-layers-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    let system = root.join("etc");
+    let team = root.join("team");
+    let user = root.join("user");
+    fs::create_dir_all(system.join("conf.d")).unwrap();
+    fs::create_dir_all(&team).unwrap();
+    fs::create_dir_all(&user).unwrap();
+
+    fs::write(system.join("config.yaml"), "profiles:\n  desk: {}\n  room: {}\n").unwrap();
+    fs::write(system.join("conf.d/20-b.yaml"), "profiles:\n  desk: {extends: room}\n").unwrap();
+    fs::write(system.join("conf.d/10-a.yaml"), "profiles:\n  desk: {}\n").unwrap();
+    fs::write(team.join("config.yaml"), "profiles:\n  room: {}\n").unwrap();
+    fs::write(user.join("config.yaml"), "profiles:\n  home: {}\n").unwrap();
+
+    let sources = ConfigSources::from_dirs(std::slice::from_ref(&system), Some(&team), &user, user.join("config.yaml"));
+    assert_eq!(sources.layers, [
+        system.join("config.yaml"),
+        system.join("conf.d/10-a.yaml"),
+        system.join("conf.d/20-b.yaml"),
+        team.join("config.yaml"),
+    ]);
+
+    let mut database = sources.load().unwrap();
+    let merged = database.merged();
+    assert_eq!(merged.profiles.keys().collect::<Vec<_>>(), ["desk", "room", "home"]);
+    assert_eq!(merged.profiles["desk"].extends.as_ref().unwrap(), "room");
+    assert_eq!(database.origins("room"), [system.join("config.yaml"), team.join("config.yaml")]);
+
+    // Shared profiles can be copied, but not changed
+    assert!(database.remove("desk").is_err());
+    database.copy("desk", "my-desk", false).unwrap();
+    assert!(database.user().profiles.contains_key("my-desk"));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn atomic_save_and_lock() {
+    use std::fs;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    let root = std::env::temp_dir().join(format!("quickrandr-save-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let path = root.join("config.yaml");
+
+    quickrandr::save_file(&path, "first").unwrap();
+    quickrandr::save_file(&path, "second").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+    assert_eq!(fs::read_dir(&root).unwrap().count(), 1);
+
+    let lock_path = root.join("config.yaml.lock");
+    let lock = quickrandr::FileLock::acquire(&lock_path).unwrap();
+    let released = Arc::new(AtomicBool::new(false));
+    let waiter = {
+        let released = released.clone();
+        let lock_path = lock_path.clone();
+        thread::spawn(move || {
+            let _lock = quickrandr::FileLock::acquire(&lock_path).unwrap();
+            assert!(released.load(Ordering::SeqCst));
+        })
+    };
+    thread::sleep(Duration::from_millis(100));
+    released.store(true, Ordering::SeqCst);
+    drop(lock);
+    waiter.join().unwrap();
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn format_roundtrip() {
+    use quickrandr::format::Format;
+    use std::path::Path;
+
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs, output_names };
+    let mut profile = quickrandr::capture_profile(&state);
+    profile.extends = Some("desk".to_string());
+    profile.outputs[0].enabled = false;
+
+    let yaml = quickrandr::generate_profile(&profile, Format::Yaml).unwrap();
+    let mut contents = yaml.clone();
+    for &(from, to) in &[(Format::Yaml, Format::Toml), (Format::Toml, Format::Json), (Format::Json, Format::Yaml)] {
+        let parsed = quickrandr::parse_profile(&contents, from).unwrap();
+        contents = quickrandr::generate_profile(&parsed, to).unwrap();
+    }
+    assert_eq!(contents, yaml);
+
+    // Profiles keep the order they are declared in, which breaks ties for --auto
+    let mut database = quickrandr::database::Database::default();
+    for name in ["desk", "child", "beamer"] {
+        database.insert(name, profile.clone(), false).unwrap();
+    }
+    let yaml = database.generate(Format::Yaml).unwrap();
+    let mut contents = yaml.clone();
+    for &(from, to) in &[(Format::Yaml, Format::Toml), (Format::Toml, Format::Json), (Format::Json, Format::Yaml)] {
+        let parsed = quickrandr::database::Database::parse(&contents, from).unwrap();
+        assert_eq!(parsed.profiles.keys().collect::<Vec<_>>(), ["desk", "child", "beamer"]);
+        contents = parsed.generate(to).unwrap();
+    }
+    assert_eq!(contents, yaml);
+
+    assert_eq!(Format::from_path(Path::new("layout.TOML")), Format::Toml);
+    assert_eq!(Format::from_path(Path::new("layout.json")), Format::Json);
+    assert_eq!(Format::from_path(Path::new("layout")), Format::Yaml);
+}
+
+#[test]
+fn edits_keep_comments_and_order() {
+    use std::fs;
+    use quickrandr::database::ConfigSources;
+
+    let root = std::env::temp_dir().join(format!("quickrandr-edit-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(&root).unwrap();
+    let path = root.join("config.yaml");
+    fs::write(&path, "\
+# My monitors
+profiles:
+  work:
+    outputs:
+      - display_name: DELL U2415   # left of the laptop
+        connector: DP-1
+        mode: 1920x1200
+        position: 0x0
+        orientation: Normal
+        primary: true
+  home:
+    extends: work  # same desk at home
+").unwrap();
+
+    let sources = ConfigSources::single(path.clone());
+    let mut database = sources.load().unwrap();
+    database.get_mut("work").unwrap().set("DP-1.position", "1920x0").unwrap();
+    database.rename("home", "flat", false).unwrap();
+    database.save().unwrap();
+
+    assert_eq!(fs::read_to_string(&path).unwrap(), "\
+# My monitors
+profiles:
+  work:
+    outputs:
+      - display_name: DELL U2415   # left of the laptop
+        connector: DP-1
+        mode: 1920x1200
+        position: 1920x0
+        orientation: Normal
+        primary: true
+  flat:
+    extends: work  # same desk at home
+");
+
+    let mut profile = database.merged().profiles["work"].clone();
+    assert!(profile.set("HDMI-1.mode", "800x600").is_err());
+    assert!(profile.set("DP-1.orientation", "sideways").is_err());
+    profile.set("DELL U2415.orientation", "left").unwrap();
+    assert_eq!(profile.outputs[0].orientation, quickrandr::Orientation::Left);
+
+    // Profiles nobody edited keep their spelling, defaults and comments
+    let original = "\
+profiles:
+  a:
+    outputs:
+      - display_name: DELL U2415  # desk
+        connector: DP-1
+        mode: 1920x1200
+        position: +10+0
+        scale: 1.50
+        orientation: Normal
+        primary: true
+      # the beamer
+      - display_name: BenQ
+        connector: HDMI-1
+        mode: 1024x768
+        position: 1930x0
+        orientation: Normal
+        primary: false
+  b:
+    outputs: []   # todo
+  laptop: {outputs: []}
+";
+    fs::write(&path, original).unwrap();
+    let mut database = sources.load().unwrap();
+    database.get_mut("b").unwrap().set("dpi", "100").unwrap();
+    let text = database.generate().unwrap();
+    assert_eq!(text, original.replace("    outputs: []   # todo\n", "    outputs: []   # todo\n    dpi:\n      value: 100\n"));
+
+    // Inserting and deleting profiles leaves the others alone
+    let mut database = sources.load().unwrap();
+    database.copy("laptop", "c", false).unwrap();
+    database.remove("b").unwrap();
+    database.rename("laptop", "notebook", false).unwrap();
+    assert_eq!(database.generate().unwrap(), original
+        .replace("  b:\n    outputs: []   # todo\n", "")
+        .replace("  laptop:", "  notebook:") + "  c: {}\n");
+
+    // Sequences shrink and grow around the items that stay
+    let mut database = sources.load().unwrap();
+    let outputs = &mut database.get_mut("a").unwrap().outputs;
+    let dell = outputs.remove(0);
+    outputs.push(quickrandr::MonitorConfig { display_name: "LG".to_string(), ..dell });
+    let text = database.generate().unwrap();
+    assert!(text.starts_with("\
+profiles:
+  a:
+    outputs:
+      # the beamer
+      - display_name: BenQ
+        connector: HDMI-1
+        mode: 1024x768
+        position: 1930x0
+        orientation: Normal
+        primary: false
+      - display_name: LG
+"), "{}", text);
+    assert!(text.ends_with("  b:\n    outputs: []   # todo\n  laptop: {outputs: []}\n"), "{}", text);
+    assert_eq!(quickrandr::database::Database::parse(&text, quickrandr::format::Format::Yaml).unwrap()
+        .profiles["a"].outputs[1].scale, Some(1.5));
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn relative_placement() {
+    use quickrandr::placement::solve;
+    use quickrandr::MonitorConfig;
+
+    fn monitor(connector: &str, mode: &str) -> MonitorConfig {
+        MonitorConfig {
+            display_name: connector.to_string(),
+            connector: Some(connector.to_string()),
+            mode: mode.parse().ok(),
+            ..MonitorConfig::default()
+        }
+    }
+
+    let laptop = monitor("eDP-1", "1366x768");
+    let mut dell = monitor("DP-1", "1920x1200");
+    dell.position = "0x0".parse().ok();
+    let mut laptop_below = laptop.clone();
+    laptop_below.place(quickrandr::placement::Side::RightOf, "DP-1");
+    laptop_below.align = Some(quickrandr::placement::Align::Bottom);
+    assert_eq!(solve(&[&dell, &laptop_below]).unwrap(), [(0, 0), (1920, 432)]);
+
+    // Placing to the left shifts everything back into positive coordinates
+    let mut left = monitor("HDMI-1", "1280x1024");
+    left.left_of = Some("DP-1".to_string());
+    left.align = Some(quickrandr::placement::Align::Center);
+    left.offset = 10;
+    assert_eq!(solve(&[&dell, &left]).unwrap(), [(1280, 0), (0, 98)]);
+
+    let mut other = left.clone();
+    other.connector = Some("HDMI-2".to_string());
+    other.display_name = "HDMI-2".to_string();
+    let err = solve(&[&dell, &left, &other]).unwrap_err().to_string();
+    assert_eq!(err, "cannot place monitors: HDMI-1 left_of DP-1 overlaps HDMI-2 left_of DP-1");
+
+    let mut a = monitor("DP-1", "1920x1200");
+    a.below = Some("DP-2".to_string());
+    let mut b = monitor("DP-2", "1920x1200");
+    b.above = Some("DP-1".to_string());
+    let err = solve(&[&a, &b]).unwrap_err().to_string();
+    assert_eq!(err, "cannot place monitors: circular placement: DP-1 below DP-2, DP-2 above DP-1");
+
+    a.right_of = Some("DP-2".to_string());
+    let err = solve(&[&a, &b]).unwrap_err().to_string();
+    assert_eq!(err, "cannot place monitors: DP-1: right_of DP-2 conflicts with below DP-2");
+}
+
+#[test]
+fn presets() {
+    use quickrandr::preset::{Preset, PresetConfig};
+
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs, output_names };
+    let lvds = &state.connected_outputs[2];
+    assert!(lvds.is_internal());
+    assert_eq!(lvds.modes.len(), 11);
+    assert_eq!(lvds.preferred_mode().unwrap().name, "1366x768");
+    assert_eq!(state.connected_outputs[0].preferred_mode().unwrap().refresh, 59.95);
+
+    let mut row = PresetConfig::new(Preset::ExtendRow);
+    row.order = vec!["LVDS1".to_string()];
+    row.align = Some(quickrandr::placement::Align::Bottom);
+    let plan = quickrandr::plan_profile(&row.generate(&state.connected_outputs).unwrap(), &state).unwrap();
+    assert_eq!(plan.to_string(), "\
+        xrandr --output DP1 --off --output HDMI1 --off --output HDMI2 --off --output HDMI3 --off --output VGA1 --off\n\
+        xrandr --output LVDS1 --mode 1366x768 --pos 0x432 --rotate normal --primary \
+        --output DP2 --mode 1920x1200 --pos 1366x0 --rotate normal \
+        --output DP3 --mode 1920x1200 --pos 3286x0 --rotate normal\n");
+
+    let mirror = PresetConfig::new(Preset::Mirror).generate(&state.connected_outputs).unwrap();
+    assert!(mirror.outputs.iter().all(|x| x.mode == "1024x768".parse().ok() && x.position == "0x0".parse().ok()));
+
+    let internal = PresetConfig::new(Preset::InternalOnly).generate(&state.connected_outputs).unwrap();
+    assert_eq!(internal.outputs.len(), 1);
+    assert!(internal.outputs[0].primary);
+    let externals = PresetConfig::new(Preset::ExternalsOnly).generate(&state.connected_outputs[..1]).unwrap();
+    assert_eq!(externals.outputs[0].connector.as_ref().unwrap(), "DP2");
+    assert!(PresetConfig::new(Preset::InternalOnly).generate(&state.connected_outputs[..2]).is_err());
+}
+
+#[test]
+fn mirror_modes() {
+    use quickrandr::preset::{common_modes, Preset, PresetConfig};
+    use quickrandr::{ModeInfo, Output};
+
+    fn output(connector: &str, modes: &[(usize, usize, f64)]) -> Output {
+        Output {
+            name: connector.to_string(),
+            edid: String::new(),
+            connection_name: connector.to_string(),
+            geometry: None,
+            physical_mm: None,
+            transformation: Default::default(),
+            modes: modes.iter().enumerate().map(|(i, &(width, height, refresh))| ModeInfo {
+                name: format!("{}x{}", width, height),
+                id: format!("0x{:x}", 0x100 + i),
+                width,
+                height,
+                refresh,
+                preferred: i == 0,
+                current: false,
+                timing: None,
+            }).collect(),
+        }
+    }
+
+    let laptop = output("eDP-1", &[(1920, 1080, 144.0), (1920, 1080, 60.0), (1280, 1024, 60.0)]);
+    let projector = output("HDMI-1", &[(1920, 1080, 50.0), (1920, 1080, 60.0), (1280, 1024, 75.0)]);
+    let modes = common_modes(&[&laptop, &projector]).unwrap();
+    assert_eq!((modes[0].refresh, modes[1].refresh), (60.0, 60.0));
+
+    // Same resolution, but no common refresh rate
+    let tv = output("HDMI-2", &[(1920, 1080, 50.0)]);
+    let modes = common_modes(&[&laptop, &tv]).unwrap();
+    assert_eq!((modes[0].refresh, modes[1].refresh), (144.0, 50.0));
+
+    // A larger common resolution wins over a smaller one with a shared rate
+    let fast = output("eDP-1", &[(1920, 1080, 144.0), (1024, 768, 60.0)]);
+    let slow = output("HDMI-1", &[(1920, 1080, 50.0), (1024, 768, 60.0)]);
+    let modes = common_modes(&[&fast, &slow]).unwrap();
+    assert_eq!((modes[0].width, modes[0].refresh), (1920, 144.0));
+    assert_eq!((modes[1].width, modes[1].refresh), (1920, 50.0));
+
+    // Nothing in common, so the projector scales the laptop picture
+    let old = output("VGA-1", &[(1024, 768, 60.0)]);
+    let connected = vec![laptop, old];
+    assert!(common_modes(&[&connected[0], &connected[1]]).is_none());
+    let profile = PresetConfig::new(Preset::Mirror).generate(&connected).unwrap();
+    let state = quickrandr::ConfigAndXrandr {
+        connected_outputs: connected,
+        output_names: vec!["VGA-1".to_string(), "eDP-1".to_string()],
+    };
+    assert_eq!(quickrandr::plan_profile(&profile, &state).unwrap().to_string(), "\
+        xrandr --output eDP-1 --mode 1920x1080 --pos 0x0 --rotate normal --primary \
+        --output VGA-1 --mode 1024x768 --scale-from 1920x1080 --same-as eDP-1 --rotate normal\n");
+}
+
+#[test]
+fn auto_scoring() {
+    use quickrandr::auto::{choose, score, AutoConfig, Choice, LastUsed, MatchKind};
+    use quickrandr::database::Database;
+
+    let (connected, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs: connected.clone(), output_names };
+    let desk = quickrandr::capture_profile(&state);
+    let mut left_only = desk.clone();
+    left_only.outputs.truncate(1);
+
+    assert_eq!(score(&desk, &connected[..2]).0, MatchKind::Exact);
+    assert_eq!(score(&desk, &connected[..1]).0, MatchKind::Superset);
+    assert_eq!(score(&desk, &connected).0, MatchKind::Subset);
+    assert_eq!(score(&desk, &connected[2..]).0, MatchKind::None);
+
+    // The other monitor of the same model stands in for the saved one
+    let (kind, adapted) = score(&left_only, &connected[..1]);
+    assert_eq!(kind, MatchKind::SameModels);
+    assert_eq!(adapted.outputs[0].edid.as_ref(), Some(&connected[0].edid));
+
+    let mut database = Database::default();
+    database.insert("desk", desk.clone(), false).unwrap();
+    database.insert("desk-copy", desk.clone(), false).unwrap();
+    let mut usage = LastUsed::new();
+    let context = quickrandr::condition::Context::read(std::path::Path::new("/nonexistent"), 0);
+
+    let name = |x: (Choice, quickrandr::Profile)| match x.0 {
+        Choice::Profile(name, _) | Choice::Fallback(name) => name,
+        Choice::Preset(preset) => preset.preset.to_string(),
+    };
+    assert_eq!(name(choose(&database, &connected[..2], &usage, &context).unwrap()), "desk");
+    usage.insert("desk-copy".to_string(), 1000);
+    assert_eq!(name(choose(&database, &connected[..2], &usage, &context).unwrap()), "desk-copy");
+    database.get_mut("desk").unwrap().priority = 1;
+    assert_eq!(name(choose(&database, &connected[..2], &usage, &context).unwrap()), "desk");
+
+    // A subset is below the default threshold
+    assert_eq!(name(choose(&database, &connected, &usage, &context).unwrap()), "extend-row");
+    database.auto = Some(AutoConfig { threshold: Some(40), ..AutoConfig::default() });
+    assert_eq!(name(choose(&database, &connected, &usage, &context).unwrap()), "desk");
+    database.auto = Some(AutoConfig { fallback_profile: Some("desk-copy".to_string()), ..AutoConfig::default() });
+    assert_eq!(name(choose(&database, &connected, &usage, &context).unwrap()), "desk-copy");
+}
+
+#[test]
+fn match_rules() {
+    use quickrandr::auto::{score, MatchKind};
+    use quickrandr::database::Database;
+    use quickrandr::format::Format;
+
+    let (connected, _) = quickrandr::parse_xrandr(EXAMPLE3);
+    let database = Database::parse(r#"
+profiles:
+  standard-desk:
+    outputs:
+      - display_name: left
+        match: {vendor: del, model: "DELL U24*", connector: "DP*"}
+        mode: 1920x1200
+        position: 0x0
+        orientation: Normal
+        primary: false
+      - display_name: right
+        match: {external: true}
+        mode: 1920x1200
+        right_of: left
+        orientation: Normal
+        primary: true
+  broken:
+    outputs:
+      - display_name: any
+        match: {serial: "(["}
+        mode: 1920x1200
+        orientation: Normal
+        primary: true
+"#, Format::Yaml).unwrap();
+
+    let desk = database.resolve("standard-desk").unwrap();
+    assert_eq!(score(&desk, &connected[..2]).0, MatchKind::Exact);
+    let matched = quickrandr::match_outputs(&desk, &connected);
+    assert_eq!(matched.len(), 2);
+    assert_eq!(matched[0].1.connection_name, "DP2");
+    assert_eq!(matched[1].1.connection_name, "DP3");
+
+    let internal_only = Database::parse(r#"
+profiles:
+  laptop:
+    outputs:
+      - display_name: panel
+        match: {connector: "LVDS*", external: false}
+        mode: 1366x768
+        orientation: Normal
+        primary: true
+"#, Format::Yaml).unwrap().resolve("laptop").unwrap();
+    assert_eq!(score(&internal_only, &connected[2..]).0, MatchKind::Exact);
+
+    let err = database.resolve("broken").unwrap_err().to_string();
+    assert!(err.starts_with("invalid profile: serial pattern (["), "{}", err);
+}
+
+#[test]
+fn roles() {
+    use quickrandr::database::Database;
+    use quickrandr::format::Format;
+
+    let (connected, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    assert_eq!(connected.iter().map(|x| x.is_internal()).collect::<Vec<_>>(), [false, false, true]);
+
+    let database = Database::parse(r#"
+profiles:
+  docked:
+    outputs:
+      - display_name: panel
+        role: internal
+        mode: 1366x768
+        position: 0x0
+        orientation: Normal
+        primary: false
+      - display_name: second
+        role: external[1]
+        mode: 1920x1200
+        right_of: external[0]
+        orientation: Normal
+        primary: false
+      - display_name: first
+        role: external[0]
+        mode: 1920x1200
+        right_of: internal
+        orientation: Normal
+        primary: true
+"#, Format::Yaml).unwrap();
+    let docked = database.resolve("docked").unwrap();
+    let state = quickrandr::ConfigAndXrandr { connected_outputs: connected, output_names };
+    assert_eq!(quickrandr::plan_profile(&docked, &state).unwrap().to_string(), "\
+        xrandr --output DP1 --off --output HDMI1 --off --output HDMI2 --off --output HDMI3 --off --output VGA1 --off\n\
+        xrandr --output LVDS1 --mode 1366x768 --pos 0x0 --rotate normal \
+        --output DP3 --mode 1920x1200 --pos 3286x0 --rotate normal \
+        --output DP2 --mode 1920x1200 --pos 1366x0 --rotate normal --primary\n");
+
+    let err = Database::parse("profiles:\n  x:\n    outputs:\n      - {display_name: a, role: \"external[x]\", \
+                               mode: 1x1, orientation: Normal, primary: true}\n", Format::Yaml).unwrap_err();
+    assert!(err.to_string().contains("unknown role external[x]"), "{}", err);
+}
+
+#[test]
+fn conditions() {
+    use quickrandr::auto::{choose, Choice, LastUsed};
+    use quickrandr::condition::{Context, Lid};
+    use quickrandr::database::Database;
+    use quickrandr::format::Format;
+    use std::fs;
+
+    let root = std::env::temp_dir().join(format!("quickrandr-root-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("proc/acpi/button/lid/LID0")).unwrap();
+    fs::create_dir_all(root.join("proc/sys/kernel")).unwrap();
+    fs::create_dir_all(root.join("sys/class/power_supply/AC")).unwrap();
+    fs::create_dir_all(root.join("sys/class/power_supply/BAT0")).unwrap();
+    fs::write(root.join("proc/acpi/button/lid/LID0/state"), "state:      closed\n").unwrap();
+    fs::write(root.join("proc/sys/kernel/hostname"), "work-laptop\n").unwrap();
+    fs::write(root.join("sys/class/power_supply/AC/online"), "0\n").unwrap();
+
+    let mut context = Context::read(&root, 23 * 60);
+    assert_eq!(context, Context { lid: Lid::Closed, ac: false, hostname: "work-laptop".to_string(), minutes: 23 * 60 });
+
+    let (connected, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs: connected.clone(), output_names };
+    let mut database = Database::parse(r#"
+profiles:
+  night:
+    extends: docked
+    when: {lid: closed, ac: false, hostname: "work-*", time: "22:00-06:00"}
+    overrides:
+      - output: internal
+        enabled: false
+  docked: {}
+"#, Format::Yaml).unwrap();
+    let docked = database.get_mut("docked").unwrap();
+    docked.outputs = quickrandr::capture_profile(&state).outputs;
+    let mut panel = docked.outputs[0].clone();
+    panel.display_name = "panel".to_string();
+    panel.edid = None;
+    panel.role = Some(quickrandr::rule::Role::Internal);
+    panel.connector = Some("LVDS1".to_string());
+    panel.mode = "1366x768".parse().ok();
+    panel.position = "3120x0".parse().ok();
+    docked.outputs.push(panel);
+
+    let name = |context: &Context| match choose(&database, &connected, &LastUsed::new(), context).unwrap().0 {
+        Choice::Profile(name, _) => name,
+        x => panic!("{}", x),
+    };
+    assert_eq!(name(&context), "night");
+    context.minutes = 12 * 60;
+    assert_eq!(name(&context), "docked");
+    context.minutes = 5 * 60;
+    context.ac = true;
+    assert_eq!(name(&context), "docked");
+
+    // Children keep the conditions and priority of their parent
+    database.get_mut("night").unwrap().priority = 2;
+    let child = quickrandr::Profile { extends: Some("night".to_string()), ..Default::default() };
+    database.insert("night-child", child, false).unwrap();
+    let resolved = database.resolve("night-child").unwrap();
+    assert_eq!((resolved.when.unwrap().count(), resolved.priority), (4, 2));
+    match choose(&database, &connected, &LastUsed::new(), &context).unwrap().0 {
+        Choice::Profile(name, _) => assert_eq!(name, "docked"),
+        x => panic!("{}", x),
+    }
+    database.remove("night-child").unwrap();
+
+    database.get_mut("night").unwrap().when.as_mut().unwrap().time = Some("25:00-06:00".to_string());
+    assert!(database.resolve("night").is_err());
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+#[test]
+fn hooks() {
+    use quickrandr::hooks::{run_post_hooks, run_pre_hooks, Hooks};
+    use std::fs;
+
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs, output_names };
+    let plan = quickrandr::plan_profile(&quickrandr::capture_profile(&state), &state).unwrap();
+
+    let out = std::env::temp_dir().join(format!("quickrandr-hook-{}", std::process::id()));
+    let global = Hooks {
+        pre: vec![format!("echo \"$QUICKRANDR_HOOK $QUICKRANDR_PROFILE\" > {}", out.display())],
+        ..Hooks::default()
+    };
+    let profile = Hooks {
+        pre: vec![format!("echo \"$QUICKRANDR_OUTPUTS|$QUICKRANDR_PRIMARY|$QUICKRANDR_GEOMETRY\" >> {}", out.display())],
+        post: vec!["exit 3".to_string()],
+        ..Hooks::default()
+    };
+    run_pre_hooks("desk", &plan, &[&global, &profile], false).unwrap();
+    assert_eq!(fs::read_to_string(&out).unwrap(), "\
+pre desk
+DP3 DP2||[{\"output\":\"DP3\",\"x\":0,\"y\":0,\"width\":1200,\"height\":1920,\"rotate\":\"left\",\"primary\":false},\
+{\"output\":\"DP2\",\"x\":1200,\"y\":352,\"width\":1920,\"height\":1200,\"rotate\":\"normal\",\"primary\":false}]
+");
+    fs::remove_file(&out).unwrap();
+
+    let errors = run_post_hooks("desk", &plan, &[&global, &profile], false);
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].to_string(), "post hook `exit 3` failed with exit code 3");
+
+    let slow = Hooks { timeout: Some(0), pre: vec!["sleep 5".to_string()], ..Hooks::default() };
+    let err = run_pre_hooks("desk", &plan, &[&slow, &profile], false).unwrap_err();
+    assert_eq!(err.to_string(), "pre hook `sleep 5` timed out after 0s");
+}
+
+#[test]
+fn dpi() {
+    use quickrandr::dpi::{resources, DpiConfig};
+
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    assert_eq!(connected_outputs[0].physical_mm, Some((520, 320)));
+    assert_eq!(connected_outputs[2].physical_mm, None);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs, output_names };
+    let plan = quickrandr::plan_profile(&quickrandr::capture_profile(&state), &state).unwrap();
+
+    let global = DpiConfig { xrdb: Some(true), ..DpiConfig::default() };
+    assert_eq!(global.dpi(&plan, &state.connected_outputs), Some(94));
+    let config = global.merge(&DpiConfig { output: Some("DP2".to_string()), ..DpiConfig::default() });
+    assert_eq!(config.dpi(&plan, &state.connected_outputs), Some(94));
+    assert_eq!(config.xrdb, Some(true));
+    let fixed = config.merge(&DpiConfig { value: Some(120), ..DpiConfig::default() });
+    assert_eq!(fixed.dpi(&plan, &state.connected_outputs), Some(120));
+    let missing = DpiConfig { output: Some("HDMI1".to_string()), ..DpiConfig::default() };
+    assert_eq!(missing.dpi(&plan, &state.connected_outputs), None);
+
+    assert_eq!(resources(120, false), "Xft.dpi: 120\n");
+    assert_eq!(resources(120, true), "Xft.dpi: 120\nXcursor.size: 30\n");
+}
+
+#[test]
+fn scaling() {
+    use quickrandr::dpi::DpiConfig;
+    use quickrandr::placement::reflow;
+    use quickrandr::Filter;
+
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs, output_names };
+    let mut profile = quickrandr::capture_profile(&state);
+    profile.dpi = Some(DpiConfig { target: Some(188), ..DpiConfig::default() });
+    profile.set("DP2.filter", "nearest").unwrap();
+    let plan = quickrandr::plan_profile(&profile, &state).unwrap();
+    assert_eq!(plan.to_string(), "\
+xrandr --output DP1 --off --output HDMI1 --off --output HDMI2 --off --output HDMI3 --off --output LVDS1 --off \
+--output VGA1 --off
+xrandr --output DP3 --mode 1920x1200 --scale 2x2 --pos 0x0 --rotate left \
+--output DP2 --mode 1920x1200 --scale 2x2 --filter nearest --pos 2400x352 --rotate normal
+");
+    assert_eq!((plan.outputs[1].width, plan.outputs[1].height), (3840, 2400));
+    assert_eq!(DpiConfig::default().dpi(&plan, &state.connected_outputs), Some(188));
+
+    // An explicit scale is kept, the other monitor moves along
+    profile.set("DP3.scale", "1").unwrap();
+    let plan = quickrandr::plan_profile(&profile, &state).unwrap();
+    assert_eq!(plan.outputs[1].x, 1200);
+    profile.set("DP3.scale_from", "1200x1920").unwrap();
+    assert!(quickrandr::plan_profile(&profile, &state).is_err());
+
+    // Monitors below and to the right keep touching, separate ones stay put
+    let positions = [(0, 0), (1920, 0), (0, 1080), (5000, 5000)];
+    let old = [(1920, 1080), (1920, 1080), (1920, 1080), (100, 100)];
+    let new = [(3840, 2160), (1920, 1080), (1920, 1080), (100, 100)];
+    assert_eq!(reflow(&positions, &old, &new), vec![(0, 0), (3840, 0), (0, 2160), (5000, 5000)]);
+    assert_eq!(Filter::from_name("bilinear"), Some(Filter::Bilinear));
+}
+
+#[test]
+fn transformations() {
+    use quickrandr::transform::{Panning, Reflect, Transform};
+    use std::convert::TryFrom;
+
+    // DP2 reflected and panning, DP3 with a keystone correction
+    let text = EXAMPLE3.replace(
+        "DP2 connected 1920x1200+1200+352 (0x164) normal (normal left inverted right x axis y axis) 520mm x 320mm",
+        "DP2 connected 1920x1200+1500+352 (0x164) normal X and Y axis (normal left inverted right x axis y axis) \
+         520mm x 320mm panning 3840x1200+1200+352 tracking 3840x1200+1200+352 border 0/0/0/0");
+    let at = text.find("DP3 connected").unwrap();
+    let text = format!("{}{}", &text[..at], text[at..].replacen(
+        "Transform:  1.000000 0.000000 0.000000\n\t            0.000000 1.000000 0.000000\n\t            0.000000 0.000000 1.000000\n\t           filter:",
+        "Transform:  1.000000 0.200000 0.000000\n\t            0.000000 1.000000 0.000000\n\t            0.000000 0.000000 1.000000\n\t           filter: bilinear",
+        1));
+
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(&text);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs, output_names };
+    let profile = quickrandr::capture_profile(&state);
+    let dp3 = &profile.outputs[0];
+    assert_eq!(dp3.transform, Some(Transform([1.0, 0.2, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 1.0])));
+    assert_eq!(dp3.filter, Some(quickrandr::Filter::Bilinear));
+    let dp2 = &profile.outputs[1];
+    assert_eq!(dp2.reflect, Some(Reflect::XY));
+    assert_eq!(dp2.panning.unwrap().to_string(), "3840x1200+1200+352");
+    assert_eq!(dp2.position, "1200x352".parse().ok());
+
+    let plan = quickrandr::plan_profile(&profile, &state).unwrap();
+    assert_eq!(plan.to_string(), "\
+xrandr --output DP1 --off --output HDMI1 --off --output HDMI2 --off --output HDMI3 --off --output LVDS1 --off \
+--output VGA1 --off
+xrandr --output DP3 --mode 1920x1200 --transform 1,0.2,0,0,1,0,0,0,1 --filter bilinear --pos 0x0 --rotate left \
+--output DP2 --mode 1920x1200 --reflect xy --panning 3840x1200+1200+352 --pos 1200x352 --rotate normal
+");
+    // The shear widens DP3 by a fifth of its height
+    assert_eq!((plan.outputs[0].width, plan.outputs[0].height), (1584, 1920));
+
+    // Loading a profile without them resets what the outputs have now
+    let plain = quickrandr::capture_profile(&quickrandr::ConfigAndXrandr {
+        connected_outputs: quickrandr::parse_xrandr(EXAMPLE3).0,
+        output_names: vec![],
+    });
+    let plan = quickrandr::plan_profile(&plain, &state).unwrap();
+    assert!(plan.to_string().contains("--transform none --pos 0x0"));
+    assert!(plan.to_string().contains("--reflect normal --panning 0x0 --pos 1200x352"));
+
+    let panning = Panning::try_from("3840x2160/1920x1080+0+0/0/10/0/10".to_string()).unwrap();
+    assert_eq!(panning.to_string(), "3840x2160+0+0/1920x1080+0+0/0/10/0/10");
+    assert!(Panning::try_from("3840x2160/1920x1080/1/2".to_string()).is_err());
+    assert!(Transform::try_from("1,0,0".to_string()).is_err());
+}
+
+#[test]
+fn primary_order() {
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs, output_names };
+    let mut profile = quickrandr::capture_profile(&state);
+    let primary = |profile: &quickrandr::Profile| -> Vec<String> {
+        let plan = quickrandr::plan_profile(profile, &state).unwrap();
+        plan.outputs.iter().filter(|x| x.primary).map(|x| x.output.clone()).collect()
+    };
+    assert!(primary(&profile).is_empty());
+
+    // The panel is off, so the next one takes over
+    profile.set("primary_order", "internal, DP2, DP3").unwrap();
+    assert_eq!(profile.primary_order, ["internal", "DP2", "DP3"]);
+    assert_eq!(primary(&profile), ["DP2"]);
+    profile.set("DP3.primary", "true").unwrap();
+    assert_eq!(primary(&profile), ["DP3"]);
+    assert!(quickrandr::plan_profile(&profile, &state).unwrap().to_string().contains("--rotate left --primary"));
+}
+
+#[test]
+fn other_monitors() {
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs, output_names };
+    let mut profile = quickrandr::capture_profile(&state);
+    profile.outputs.retain(|x| x.connector.as_deref() == Some("DP2"));
+
+    // DP3 stays on, but moves down to make room above it
+    profile.set("others", "keep").unwrap();
+    profile.set("DP2.above", "DP3").unwrap();
+    let plan = quickrandr::plan_profile(&profile, &state).unwrap();
+    assert_eq!(plan.to_string(), "\
+xrandr --output DP1 --off --output HDMI1 --off --output HDMI2 --off --output HDMI3 --off --output VGA1 --off
+xrandr --output DP2 --mode 1920x1200 --pos 0x0 --rotate normal --output DP3 --pos 0x1200
+");
+    assert_eq!(plan.outputs.len(), 2);
+
+    profile.set("DP2.position", "1200x0").unwrap();
+    profile.set("others", "right-of-last").unwrap();
+    let plan = quickrandr::plan_profile(&profile, &state).unwrap();
+    assert_eq!(plan.commands[1].join(" "), "\
+--output DP2 --mode 1920x1200 --pos 1200x0 --rotate normal \
+--output DP3 --mode 1920x1200 --pos 3120x0 --rotate normal \
+--output LVDS1 --mode 1366x768 --pos 5040x0 --rotate normal");
+
+    profile.set("others", "auto").unwrap();
+    let plan = quickrandr::plan_profile(&profile, &state).unwrap();
+    assert!(plan.to_string().ends_with("--output DP3 --auto --output LVDS1 --auto\n"));
+
+    assert!(profile.set("others", "sideways").is_err());
+}
+
+#[test]
+fn legacy_profiles() {
+    use quickrandr::legacy::{convert, parse_other_args, parse_output_args, split_args};
+    use quickrandr::others::Others;
+    use quickrandr::placement::Side;
+
+    assert_eq!(split_args(r#"--set "Broadcast RGB" 'Limited 16:235' a\ b"#).unwrap(),
+               ["--set", "Broadcast RGB", "Limited 16:235", "a b"]);
+    assert!(split_args("--set \"Broadcast RGB").is_err());
+
+    let config = parse_output_args("HDMI1", "--mode 1920x1080 --rate 50 --right-of LVDS1 --rotate left \
+                                            --reflect x --scale 1.5x1.5 --set audio off --primary").unwrap();
+    assert_eq!(config.mode, "1920x1080".parse().ok());
+    assert_eq!(config.rate, Some(50.0));
+    assert_eq!(config.relation().unwrap(), Some((Side::RightOf, "LVDS1")));
+    assert_eq!(config.orientation, quickrandr::Orientation::Left);
+    assert_eq!(config.scale, Some(1.5));
+    assert_eq!(config.properties["audio"], "off");
+    assert!(config.primary && config.enabled);
+    assert!(parse_output_args("HDMI1", "--mode").is_err());
+    assert!(parse_output_args("HDMI1", "--brightness 0.5").is_err());
+
+    assert_eq!(parse_other_args("--auto").unwrap(), Others::Auto);
+    assert_eq!(parse_other_args("--off").unwrap(), Others::Off);
+    assert_eq!(parse_other_args("").unwrap(), Others::Keep);
+    let err = parse_other_args("--auto --right-of LVDS1").unwrap_err();
+    assert!(err.to_string().contains("no `others` policy for --right-of"), "{}", err);
+    let err = parse_other_args("--mode 1024x768 --same-as LVDS1").unwrap_err();
+    assert!(err.to_string().contains("no `others` policy for --mode"), "{}", err);
+    assert!(parse_other_args("--brightness 0.5").is_err());
+
+    let database = convert(&quickrandr::parse_json(r#"{
+        "autoprofiles": [],
+        "profiles": {
+            "default": {
+                "outputs": {"LVDS1": "--auto --primary", "HDMI1": "--off"},
+                "other_outputs": "--auto"
+            }
+        }
+    }"#).unwrap()).unwrap();
+    let profile = database.get("default").unwrap();
+    assert_eq!(profile.others, Some(Others::Auto));
+
+    // The panel gets its preferred mode, as with --auto
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs, output_names };
+    let plan = quickrandr::plan_profile(profile, &state).unwrap();
+    assert_eq!(plan.commands[1].join(" "), "\
+--output LVDS1 --mode 1366x768 --pos 0x0 --rotate normal --primary \
+--output DP2 --auto --output DP3 --auto");
+
+    let err = convert(&quickrandr::parse_json(r#"{
+        "autoprofiles": [],
+        "profiles": {"default": {"outputs": {}, "other_outputs": "--auto --right-of LVDS1"}}
+    }"#).unwrap()).unwrap_err();
+    assert!(err.to_string().contains("--right-of"), "{}", err);
+}
+
+#[test]
+fn modes_and_positions() {
+    use quickrandr::database::Database;
+    use quickrandr::format::Format;
+    use quickrandr::mode::{Mode, Position};
+    use quickrandr::{Geometry, Orientation};
+
+    let mode: Mode = "1920x1080@59.95".parse().unwrap();
+    assert_eq!((mode.width, mode.height, mode.refresh), (1920, 1080, Some(59.95)));
+    assert_eq!(mode.name(), "1920x1080");
+    assert_eq!(mode.to_string(), "1920x1080@59.95");
+    let interlaced: Mode = "1920x1080i".parse().unwrap();
+    assert_eq!((interlaced.flags.as_str(), interlaced.refresh), ("i", None));
+    assert_eq!(interlaced.to_string(), "1920x1080i");
+    assert!("1920".parse::<Mode>().is_err());
+    assert!("1920x1080@fast".parse::<Mode>().is_err());
+
+    assert_eq!("+1200+352".parse(), Ok(Position::new(1200, 352)));
+    assert_eq!("-100+0".parse(), Ok(Position::new(-100, 0)));
+    assert_eq!("1200x352".parse(), Ok(Position::new(1200, 352)));
+    assert!("1200".parse::<Position>().is_err());
+    for position in [Position::new(-100, 0), Position::new(0, -1080), Position::new(-1920, -200)] {
+        assert_eq!(position.to_string().parse(), Ok(position));
+    }
+
+    let database = Database::parse(r#"
+profiles:
+  desk:
+    outputs:
+      - display_name: DELL U2415
+        mode: 1920x1200@59.95
+        position: +1200+352
+        orientation: Normal
+        primary: true
+"#, Format::Yaml).unwrap();
+    let config = &database.profiles["desk"].outputs[0];
+    assert_eq!(config.position, Some(Position::new(1200, 352)));
+    let yaml = database.generate(Format::Yaml).unwrap();
+    assert!(yaml.contains("mode: 1920x1200@59.95") && yaml.contains("position: 1200x352"), "{}", yaml);
+
+    assert_eq!(Geometry::rotate((1920, 1200), &Orientation::Left), (1200, 1920));
+    assert_eq!(Geometry::rotate((1920, 1200), &Orientation::Inverted), (1920, 1200));
+}
+
+#[test]
+fn custom_modes() {
+    use quickrandr::legacy::parse_output_args;
+    use quickrandr::modeline::{cvt, Modeline, Timing};
+
+    assert_eq!(cvt(1920, 1080, 60.0, false).to_string(),
+        "173.00 1920 2048 2248 2576 1080 1083 1088 1120 -hsync +vsync");
+    assert_eq!(cvt(2560, 1440, 60.0, true).to_string(),
+        "241.50 2560 2608 2640 2720 1440 1443 1448 1481 +hsync -vsync");
+
+    let modeline: Modeline = r#"Modeline "2560x1440R" 241.50 2560 2608 2640 2720 1440 1443 1448 1481 +hsync -vsync"#
+        .parse().unwrap();
+    assert_eq!(modeline, cvt(2560, 1440, 60.0, true));
+    assert!((modeline.refresh() - 59.95).abs() < 0.01);
+    assert_eq!("cvt-rb".parse(), Ok(Timing::CvtReduced));
+    assert!("2560 1440".parse::<Timing>().is_err());
+
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs, output_names };
+    let mut config = parse_output_args("DP2", "--mode 2560x1440@75").unwrap();
+    config.modeline = Some(Timing::CvtReduced);
+    let mut profile = quickrandr::Profile { outputs: vec![config.clone()], ..Default::default() };
+    let plan = quickrandr::plan_profile(&profile, &state).unwrap();
+    let commands: Vec<String> = plan.commands.iter().map(|x| x.join(" ")).collect();
+    assert_eq!(commands[1..], [
+        format!("--newmode 2560x1440_75.00 {}", cvt(2560, 1440, 75.0, true)),
+        "--addmode DP2 2560x1440_75.00".to_string(),
+        "--output DP2 --mode 2560x1440_75.00 --pos 0x0 --rotate normal".to_string(),
+    ]);
+
+    // A listed mode is used as it is
+    config.mode = "1920x1200".parse().ok();
+    profile.outputs = vec![config];
+    let plan = quickrandr::plan_profile(&profile, &state).unwrap();
+    assert_eq!(plan.commands[1].join(" "), "--output DP2 --mode 1920x1200 --pos 0x0 --rotate normal");
+}
+
+#[test]
+fn mode_timings() {
+    use quickrandr::legacy::parse_output_args;
+
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE1);
+    let lvds = connected_outputs.iter().find(|x| x.connection_name == "LVDS1").unwrap();
+    let current = lvds.modes.iter().find(|x| x.current).unwrap();
+    assert!(current.preferred && current.name == "1366x768");
+    let slow = lvds.modes.iter().find(|x| x.id == "0x147").unwrap();
+    let timing = slow.timing.clone().unwrap();
+    assert_eq!(timing.to_string(), "36.00 800 824 896 1024 600 601 603 625 +HSync +VSync");
+    assert!((timing.refresh() - slow.refresh).abs() < 0.01);
+
+    let (outputs, _) = quickrandr::parse_xrandr(EXAMPLE2);
+    let dp2 = outputs.iter().find(|x| x.connection_name == "DP2").unwrap();
+    let interlaced = dp2.modes.iter().find(|x| x.id == "0x168").unwrap();
+    assert_eq!((interlaced.name.as_str(), interlaced.height), ("1920x1080i", 1080));
+    let timing = interlaced.timing.as_ref().unwrap();
+    assert!(timing.flags.contains(&"Interlace".to_string()));
+    assert!((timing.refresh() - 60.0).abs() < 0.01);
+
+    // The name alone would pick the first 800x600
+    let mut config = parse_output_args("LVDS1", "--mode 800x600").unwrap();
+    config.timing = slow.timing.clone();
+    let profile = quickrandr::Profile { outputs: vec![config.clone()], ..Default::default() };
+    let state = quickrandr::ConfigAndXrandr { connected_outputs: connected_outputs.clone(), output_names };
+    let plan = quickrandr::plan_profile(&profile, &state).unwrap();
+    assert!(plan.commands.last().unwrap().join(" ").starts_with("--output LVDS1 --mode 0x147 --pos 0x0"));
+
+    // Only modes that share their name are saved with their timing
+    let mut lvds = lvds.clone();
+    let unchanged = quickrandr::capture_output(&lvds).unwrap();
+    for mode in &mut lvds.modes {
+        mode.current = mode.id == "0x147";
+    }
+    assert_eq!(quickrandr::capture_output(&lvds).unwrap().timing, slow.timing);
+    assert_eq!(unchanged.timing, None);
+}
+
+#[test]
+fn nearest_modes() {
+    use quickrandr::legacy::parse_output_args;
+
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let dp2 = connected_outputs.iter().find(|x| x.connection_name == "DP2").unwrap();
+    let nearest = |mode: &str| dp2.nearest_mode(&mode.parse().unwrap(), None).map(|x| x.id.as_str());
+    assert_eq!(nearest("1024x768@60"), Some("0x143"));
+    assert_eq!(nearest("1920x1080i"), Some("0x168"));
+    // Same size, progressive like the requested one
+    assert_eq!(nearest("1920x1080@75"), Some("0x165"));
+    // Same aspect ratio, closest area
+    assert_eq!(nearest("3840x2400"), Some("0x164"));
+    assert_eq!(nearest("1440x900"), Some("0x164"));
+    // Preferred
+    assert_eq!(nearest("2560x1080"), Some("0x164"));
+
+    let mut config = parse_output_args("DP2", "--mode 1920x1080 --rate 75").unwrap();
+    let state = quickrandr::ConfigAndXrandr { connected_outputs: connected_outputs.clone(), output_names };
+    let profile = quickrandr::Profile { outputs: vec![config.clone()], ..Default::default() };
+    let plan = quickrandr::plan_profile(&profile, &state).unwrap();
+    assert_eq!(plan.notes, ["DP2: 1920x1080@75 is not available, using 1920x1080@60"]);
+    assert_eq!(plan.commands[1].join(" "), "--output DP2 --mode 1920x1080 --rate 60.00 --pos 0x0 --rotate normal");
+    assert!(plan.to_string().starts_with("# DP2: 1920x1080@75 is not available"));
+
+    config.rate = Some(59.94);
+    let profile = quickrandr::Profile { outputs: vec![config], ..Default::default() };
+    assert!(quickrandr::plan_profile(&profile, &state).unwrap().notes.is_empty());
+}
+
+#[test]
+fn refresh_policies() {
+    use quickrandr::database::Database;
+    use quickrandr::format::Format;
+    use quickrandr::legacy::parse_output_args;
+    use quickrandr::refresh::{Rate, Refresh};
+
+    let power: Refresh = "on_battery: 60, on_ac: max".parse().unwrap();
+    assert_eq!(power, Refresh::Power { on_battery: Rate::Hz(60.0), on_ac: Rate::Max });
+    assert_eq!((power.rate(false), power.rate(true)), (Rate::Hz(60.0), Rate::Max));
+    assert!("fast".parse::<Refresh>().is_err());
+
+    let database = Database::parse(r#"
+profiles:
+  desk:
+    refresh: max
+    outputs:
+      - display_name: XG2431
+        mode: 1920x1080
+        orientation: Normal
+        primary: true
+        refresh:
+          on_battery: 60
+          on_ac: max
+"#, Format::Yaml).unwrap();
+    let desk = &database.profiles["desk"];
+    assert_eq!(desk.refresh, Some(Refresh::Always(Rate::Max)));
+    assert_eq!(desk.outputs[0].refresh, Some(power));
+    let yaml = database.generate(Format::Yaml).unwrap();
+    assert!(yaml.contains("on_battery: 60") && yaml.contains("on_ac: max"), "{}", yaml);
+
+    // DP2 lists 1920x1080 at 60, 50, 59.94, 30, 25, 24, 29.97 and 23.98 Hz
+    let (connected_outputs, output_names) = quickrandr::parse_xrandr(EXAMPLE3);
+    let state = quickrandr::ConfigAndXrandr { connected_outputs, output_names };
+    let plan = |profile_refresh: Option<&str>, output_refresh: Option<&str>| {
+        let mut config = parse_output_args("DP2", "--mode 1920x1080 --rate 25").unwrap();
+        config.refresh = output_refresh.map(|x| x.parse().unwrap());
+        let profile = quickrandr::Profile {
+            refresh: profile_refresh.map(|x| x.parse().unwrap()),
+            outputs: vec![config],
+            ..Default::default()
+        };
+        let plan = quickrandr::plan_profile(&profile, &state).unwrap();
+        (plan.commands[1].join(" "), plan.notes)
+    };
+    let rate = |profile_refresh, output_refresh| {
+        let (command, _) = plan(profile_refresh, output_refresh);
+        command.split(" --rate ").nth(1).unwrap().split(' ').next().unwrap().to_string()
+    };
+    assert_eq!(rate(None, None), "25.00");
+    assert_eq!(rate(Some("saved"), None), "25.00");
+    assert_eq!(rate(Some("max"), None), "60.00");
+    assert_eq!(rate(Some("max"), Some("50")), "50.00");
+    assert_eq!(rate(None, Some("preferred")), "60.00");
+    assert_eq!(rate(None, Some("29.97")), "29.97");
+    // Planned as on AC unless applied
+    assert_eq!(rate(None, Some("on_battery: 30, on_ac: 24")), "24.00");
+
+    let (_, notes) = plan(Some("144"), None);
+    assert_eq!(notes, ["DP2: 144 Hz is not available for 1920x1080, using 60.00 Hz"]);
+}